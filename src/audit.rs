@@ -0,0 +1,132 @@
+use ::chrono::prelude::*;
+use ::log::warn;
+use ::serde::Serialize;
+use ::std::collections::HashMap;
+use ::std::fs::OpenOptions;
+use ::std::io::Write;
+use ::std::path::Path;
+
+/// What happened to a webhook request
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Decision {
+    Queued,
+    Coalesced,
+    Rejected,
+    /// The client address isn't in the `allowed_ips`
+    Blocked,
+    AuthFailed,
+    /// The client is locked out after too many failed authentications
+    LockedOut,
+    /// The delivery id has already been seen within the `replay_window`
+    Replayed,
+    /// The webhook's `rate_limit` has been reached
+    RateLimited,
+    Invalid,
+}
+
+/// A single line of the audit log
+#[derive(Serialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Local>,
+    pub remote_addr: Option<String>,
+    pub forwarded_for: Option<String>,
+    pub request_id: String,
+    pub principal: Option<String>,
+    pub auth_method: Option<String>,
+    pub webhook: String,
+    pub parameters: HashMap<String, String>,
+    pub command: Option<String>,
+    pub decision: Option<Decision>,
+    pub task_id: Option<i32>,
+}
+
+impl AuditEntry {
+    pub fn new(
+        remote_addr: Option<String>,
+        forwarded_for: Option<String>,
+        request_id: String,
+        webhook: String,
+    ) -> Self {
+        AuditEntry {
+            timestamp: Local::now(),
+            remote_addr,
+            forwarded_for,
+            request_id,
+            principal: None,
+            auth_method: None,
+            webhook,
+            parameters: HashMap::new(),
+            command: None,
+            decision: None,
+            task_id: None,
+        }
+    }
+}
+
+/// Append an entry to the audit log, if one is configured
+pub fn write_audit_entry(audit_log: &Option<impl AsRef<Path>>, entry: &AuditEntry) {
+    if let Some(path) = audit_log {
+        append_json_line(path.as_ref(), entry);
+    }
+}
+
+/// Append a value as a single json line to a file.
+/// Each line is written with a single call, so concurrent writers don't interleave.
+pub fn append_json_line(path: &Path, value: &impl Serialize) {
+    let mut line = match serde_json::to_string(value) {
+        Ok(line) => line,
+        Err(error) => {
+            warn!("Couldn't encode json line for {:?}: {}", path, error);
+            return;
+        }
+    };
+    line.push('\n');
+
+    let result = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(error) = result {
+        warn!("Couldn't write to {:?}: {}", path, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Entries are appended as one json object per line
+    fn test_append_entries() {
+        let path = std::env::temp_dir().join(format!("audit_test_{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut entry = AuditEntry::new(
+            Some("127.0.0.1".into()),
+            None,
+            "request".into(),
+            "ls".into(),
+        );
+        entry.decision = Some(Decision::AuthFailed);
+        write_audit_entry(&Some(&path), &entry);
+
+        entry.decision = Some(Decision::Queued);
+        entry.task_id = Some(1);
+        write_audit_entry(&Some(&path), &entry);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["decision"], "auth_failed");
+        assert_eq!(lines[0]["task_id"], serde_json::Value::Null);
+        assert_eq!(lines[1]["decision"], "queued");
+        assert_eq!(lines[1]["task_id"], 1);
+    }
+}
@@ -0,0 +1,94 @@
+use ::anyhow::{anyhow, Result};
+use ::chrono::prelude::*;
+use ::log::kv::{Error, Key, Value, VisitSource};
+use ::log::{LevelFilter, Log, Metadata, Record};
+use ::serde_json::{Map, Value as JsonValue};
+use ::simplelog::{Config, SimpleLogger};
+use ::std::io::Write;
+use ::std::str::FromStr;
+use ::std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::settings::Settings;
+
+/// Whether log lines are written as json objects instead of plain lines
+static JSON_FORMAT: AtomicBool = AtomicBool::new(false);
+
+/// Initialize the global logger with the `info` level and the `human` format.
+/// This happens before reading the settings, so messages of the settings aren't lost.
+pub fn init_logger() {
+    let logger = Logger {
+        human: SimpleLogger::new(LevelFilter::Trace, Config::default()),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(LevelFilter::Info);
+    }
+}
+
+/// Switch the global logger to the level and format from the settings
+pub fn configure_logger(settings: &Settings) -> Result<()> {
+    let level = LevelFilter::from_str(&settings.log_level)
+        .map_err(|_| anyhow!("Invalid log_level: {}", settings.log_level))?;
+    let json = match settings.log_format.as_str() {
+        "human" => false,
+        "json" => true,
+        format => return Err(anyhow!("Invalid log_format: {}", format)),
+    };
+
+    JSON_FORMAT.store(json, Ordering::Relaxed);
+    log::set_max_level(level);
+
+    Ok(())
+}
+
+/// Logger that writes either plain lines or a json object per line to stdout.
+/// The level is filtered via `log::max_level`.
+struct Logger {
+    human: Box<SimpleLogger>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        if !JSON_FORMAT.load(Ordering::Relaxed) {
+            self.human.log(record);
+            return;
+        }
+
+        // Key-value pairs of the log record (e.g. `webhook` or `task_id`) are added as fields
+        let mut fields = Map::new();
+        fields.insert("timestamp".into(), Local::now().to_rfc3339().into());
+        fields.insert("level".into(), record.level().to_string().into());
+        fields.insert("target".into(), record.target().into());
+        fields.insert("message".into(), record.args().to_string().into());
+        let _ = record.key_values().visit(&mut FieldVisitor(&mut fields));
+
+        let line = JsonValue::Object(fields).to_string();
+        let _ = writeln!(std::io::stdout().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        self.human.flush();
+        let _ = std::io::stdout().flush();
+    }
+}
+
+/// Collects the key-value pairs of a log record into a json object
+struct FieldVisitor<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for FieldVisitor<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), Error> {
+        let value = match value.to_i64() {
+            Some(number) => number.into(),
+            None => value.to_string().into(),
+        };
+        self.0.insert(key.to_string(), value);
+
+        Ok(())
+    }
+}
@@ -1,31 +1,56 @@
+mod audit;
+mod jwt;
+mod logging;
 mod messages;
+mod metrics;
+mod notification;
+mod password;
 mod scheduler;
+mod secrets;
 mod settings;
 mod task;
 mod web;
 
 use ::actix::prelude::*;
-use ::anyhow::Result;
+use ::anyhow::{bail, Result};
 use ::log::info;
-use ::simplelog::{Config, LevelFilter, SimpleLogger};
+use ::std::io::BufRead;
 
+use crate::logging::{configure_logger, init_logger};
+use crate::notification::Notifier;
 use crate::scheduler::Scheduler;
 use crate::settings::Settings;
 use crate::task::executor::TaskExecutor;
 use crate::web::init_web_server;
 
 fn main() -> Result<()> {
+    // Helper to create the `password_hash` of `basic_auth_users`
+    if std::env::args().nth(1).as_deref() == Some("hash-password") {
+        return print_password_hash();
+    }
+
     let system = System::new();
-    let _ = SimpleLogger::init(LevelFilter::Info, Config::default());
+    // The logger is configured after reading the settings, since they contain its configuration
+    init_logger();
     let settings = Settings::new()?;
+    configure_logger(&settings)?;
+    secrets::warn_about_inline_secrets(&settings::get_config_paths()?);
 
     // Create actix actors and path the reference of the task_executor to the scheduler
     // The scheduler will send it's own address in the StartTask payload for bidirectional communication
     info!("Starting task executor with {} workers", settings.workers);
-    let task_executor =
-        SyncArbiter::start(settings.workers, move || TaskExecutor { scheduler: None });
+    let task_executor = SyncArbiter::start(settings.workers, move || TaskExecutor);
+    metrics::WORKERS_TOTAL.set(settings.workers as i64);
+
+    // Notifications are delivered by a single thread, to keep them in order
+    let notifier_settings = settings.clone();
+    let arbiter = Arbiter::current();
+    let notifier = SyncArbiter::start(1, move || Notifier {
+        settings: notifier_settings.clone(),
+        arbiter: arbiter.clone(),
+    });
 
-    let scheduler = Scheduler::new(task_executor.clone(), settings.clone());
+    let scheduler = Scheduler::new(task_executor.clone(), notifier, settings.clone());
 
     init_web_server(scheduler.start(), settings)?;
 
@@ -33,3 +58,17 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Read a password from the first line of stdin and print its argon2 hash
+fn print_password_hash() -> Result<()> {
+    let mut password = String::new();
+    std::io::stdin().lock().read_line(&mut password)?;
+    let password = password.trim_end_matches(&['\r', '\n'][..]);
+    if password.is_empty() {
+        bail!("Please pass the password via stdin");
+    }
+
+    println!("{}", password::hash_password(password)?);
+
+    Ok(())
+}
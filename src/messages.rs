@@ -1,21 +1,90 @@
 use ::actix::prelude::*;
 use ::chrono::prelude::*;
+use ::serde::Serialize;
 use ::std::collections::HashMap;
+use ::std::path::PathBuf;
 
+use crate::notification::TaskSummary;
 use crate::scheduler::Scheduler;
+use crate::settings::{CommitStatus, Email, Notify, NotifyEvent};
+use crate::task::step::{StepResult, TaskStep};
+use crate::task::task::{Commit, Task};
 
+/// Get the task queue as json
 #[derive(Message)]
 #[rtype(result = "String")]
-pub struct GetQueue;
+pub struct GetQueue {
+    /// Only include the tasks of these webhooks. All webhooks, if it's None.
+    pub webhooks: Option<Vec<String>>,
+}
 
+/// Get aggregated statistics of the webhooks as json
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "String")]
+pub struct GetStatistics {
+    /// Only include these webhooks. All webhooks, if it's None.
+    pub webhooks: Option<Vec<String>>,
+}
+
+/// Check whether the scheduler is responsive and the worker pool isn't stalled
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct Ping;
+
+/// Get all unfinished and the most recently finished tasks
+#[derive(Message)]
+#[rtype(result = "TaskOverview")]
+pub struct GetTasks {
+    pub finished_limit: usize,
+    /// Only include the tasks of these webhooks. All webhooks, if it's None.
+    pub webhooks: Option<Vec<String>>,
+}
+
+/// The answer of the scheduler to GetTasks. Finished tasks are ordered from new to old.
+#[derive(MessageResponse, Serialize, Debug)]
+pub struct TaskOverview {
+    pub scheduled: Vec<Task>,
+    pub queued: Vec<Task>,
+    pub running: Vec<Task>,
+    pub finished: Vec<Task>,
+}
+
+/// Get a single task by its id
+#[derive(Message)]
+#[rtype(result = "Option<Task>")]
+pub struct GetTask {
+    pub task_id: i32,
+}
+
+#[derive(Message)]
+#[rtype(result = "NewTaskResult")]
 pub struct NewTask {
     pub webhook_name: String,
     pub parameters: HashMap<String, String>,
     pub cwd: String,
     pub command: String,
+    pub steps: Vec<TaskStep>,
     pub added_at: DateTime<Local>,
+    pub run_at: Option<DateTime<Local>>,
+    pub parent_id: Option<i32>,
+    pub commit: Option<Commit>,
+}
+
+/// The answer of the scheduler to a NewTask
+#[derive(MessageResponse, Debug, PartialEq)]
+pub enum NewTaskResult {
+    /// The task has been added with this id
+    Added(i32),
+    /// The task is already covered by the existing task with this id
+    Coalesced(i32),
+    /// The queue is full
+    Rejected,
+}
+
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct CancelTask {
+    pub task_id: i32,
 }
 
 #[derive(Message)]
@@ -25,6 +94,8 @@ pub struct StartTask {
     pub task_id: i32,
     pub command: String,
     pub cwd: String,
+    pub steps: Vec<TaskStep>,
+    pub log_file: Option<PathBuf>,
     pub scheduler: Addr<Scheduler>,
 }
 
@@ -36,4 +107,26 @@ pub struct TaskCompleted {
     pub exit_code: u32,
     pub stdout: String,
     pub stderr: String,
+    pub step_results: Vec<StepResult>,
+}
+
+/// Deliver a notification about a task's state change to the given targets
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendNotification {
+    pub event: NotifyEvent,
+    pub summary: TaskSummary,
+    pub targets: Vec<Notify>,
+    pub email: Option<Email>,
+    pub commit_status: Option<CommitStatus>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RetryNotification {
+    pub summary: TaskSummary,
+    pub target: Notify,
+    /// The id of the first attempt, that's reused for all retries
+    pub transaction_id: String,
+    pub attempt: u32,
 }
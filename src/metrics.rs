@@ -0,0 +1,107 @@
+use ::lazy_static::lazy_static;
+use ::prometheus::*;
+
+lazy_static! {
+    pub static ref REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "webhook_requests_total",
+        "Incoming webhook requests by webhook and authentication outcome",
+        &["webhook", "auth"]
+    )
+    .unwrap();
+    pub static ref AUTH_FAILURES: IntCounter = register_int_counter!(
+        "webhook_auth_failures_total",
+        "Requests with invalid or missing credentials"
+    )
+    .unwrap();
+    pub static ref LOCKOUTS: IntCounter = register_int_counter!(
+        "webhook_lockouts_total",
+        "Clients that have been locked out after too many failed authentications"
+    )
+    .unwrap();
+    pub static ref RATE_LIMITED: IntCounterVec = register_int_counter_vec!(
+        "webhook_rate_limited_total",
+        "Webhook requests that have been rejected due to the webhook's rate limit",
+        &["webhook"]
+    )
+    .unwrap();
+    pub static ref TASKS_ENQUEUED: IntCounterVec = register_int_counter_vec!(
+        "webhook_tasks_enqueued_total",
+        "Tasks that have been added to the queue or have been scheduled",
+        &["webhook"]
+    )
+    .unwrap();
+    pub static ref TASKS_DROPPED: IntCounterVec = register_int_counter_vec!(
+        "webhook_tasks_dropped_total",
+        "Tasks that haven't been added, either due to coalescing or a full queue",
+        &["webhook", "reason"]
+    )
+    .unwrap();
+    pub static ref TASKS_STARTED: IntCounterVec = register_int_counter_vec!(
+        "webhook_tasks_started_total",
+        "Tasks that have been dispatched to a worker",
+        &["webhook"]
+    )
+    .unwrap();
+    pub static ref TASKS_FINISHED: IntCounterVec = register_int_counter_vec!(
+        "webhook_tasks_finished_total",
+        "Finished tasks by exit code",
+        &["webhook", "exit_code"]
+    )
+    .unwrap();
+    pub static ref TASKS_QUEUED: IntGaugeVec = register_int_gauge_vec!(
+        "webhook_tasks_queued",
+        "Tasks that are currently queued",
+        &["webhook"]
+    )
+    .unwrap();
+    pub static ref TASKS_SCHEDULED: IntGaugeVec = register_int_gauge_vec!(
+        "webhook_tasks_scheduled",
+        "Tasks that are currently waiting for their run_at time",
+        &["webhook"]
+    )
+    .unwrap();
+    pub static ref TASKS_RUNNING: IntGaugeVec = register_int_gauge_vec!(
+        "webhook_tasks_running",
+        "Tasks that are currently running",
+        &["webhook"]
+    )
+    .unwrap();
+    pub static ref TASK_DURATION: HistogramVec = register_histogram_vec!(
+        "webhook_task_duration_seconds",
+        "The runtime of tasks",
+        &["webhook"],
+        exponential_buckets(0.1, 4.0, 10).unwrap()
+    )
+    .unwrap();
+    pub static ref TASK_WAIT: HistogramVec = register_histogram_vec!(
+        "webhook_task_wait_seconds",
+        "The time tasks spent in the queue before being started",
+        &["webhook"],
+        exponential_buckets(0.1, 4.0, 10).unwrap()
+    )
+    .unwrap();
+    pub static ref NOTIFICATIONS: IntCounterVec = register_int_counter_vec!(
+        "webhook_notifications_total",
+        "Notifications by delivery outcome, after all attempts",
+        &["webhook", "outcome"]
+    )
+    .unwrap();
+    pub static ref WORKERS_BUSY: IntGauge = register_int_gauge!(
+        "webhook_workers_busy",
+        "Workers that are currently executing a task"
+    )
+    .unwrap();
+    pub static ref WORKERS_TOTAL: IntGauge =
+        register_int_gauge!("webhook_workers_total", "The size of the worker pool").unwrap();
+}
+
+/// Encode all registered metrics in the Prometheus text format
+pub fn render_metrics() -> String {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    if let Err(error) = encoder.encode(&gather(), &mut buffer) {
+        return format!("Got error while encoding metrics: {:?}", error);
+    }
+
+    String::from_utf8_lossy(&buffer).to_string()
+}
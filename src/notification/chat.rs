@@ -0,0 +1,95 @@
+use ::handlebars::RenderError;
+use ::percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use ::serde_json::json;
+
+use crate::notification::{render_summary, TaskSummary};
+use crate::settings::Notify;
+
+const DEFAULT_TEMPLATE: &str = "Task {{task_id}} of webhook {{webhook}} {{event}}\
+{{#if finished_at}} with exit code {{exit_code}} after {{duration}} seconds{{/if}}";
+
+/// Render the chat message of a notification
+pub fn render_message(target: &Notify, summary: &TaskSummary) -> Result<String, RenderError> {
+    render_summary(
+        target.template.as_deref().unwrap_or(DEFAULT_TEMPLATE),
+        summary,
+    )
+}
+
+/// The body for incoming webhooks of Slack and Mattermost
+pub fn incoming_webhook_body(message: &str) -> String {
+    json!({ "text": message }).to_string()
+}
+
+/// The body of a Matrix `m.room.message` event
+pub fn matrix_body(message: &str) -> String {
+    json!({ "msgtype": "m.text", "body": message }).to_string()
+}
+
+/// The url to send a message to a Matrix room.
+/// The transaction id is reused for retries, so the homeserver can drop duplicates.
+pub fn matrix_url(base_url: &str, room_id: &str, transaction_id: &str) -> String {
+    format!(
+        "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+        base_url.trim_end_matches('/'),
+        utf8_percent_encode(room_id, NON_ALPHANUMERIC),
+        utf8_percent_encode(transaction_id, NON_ALPHANUMERIC)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{NotifyEvent, NotifyKind};
+    use std::collections::HashMap;
+
+    fn summary(event: NotifyEvent, exit_code: Option<u32>) -> TaskSummary {
+        TaskSummary {
+            webhook: "deploy".to_string(),
+            task_id: 3,
+            event,
+            parameters: HashMap::new(),
+            attempt: 1,
+            exit_code,
+            started_at: None,
+            finished_at: exit_code.map(|_| chrono::Local::now()),
+            duration: exit_code.map(|_| 1.5),
+            stdout: None,
+            stderr: None,
+            commit: None,
+        }
+    }
+
+    #[test]
+    /// The default template only mentions the exit code of finished tasks
+    fn test_default_template() {
+        let target = Notify {
+            kind: NotifyKind::Slack,
+            url: "https://hooks.slack.com/services/test".to_string(),
+            secret: None,
+            template: None,
+            room_id: None,
+            access_token: None,
+            events: Vec::new(),
+            max_attempts: 1,
+        };
+
+        let started = render_message(&target, &summary(NotifyEvent::Started, None)).unwrap();
+        assert_eq!(started, "Task 3 of webhook deploy started");
+
+        let failed = render_message(&target, &summary(NotifyEvent::Failed, Some(0))).unwrap();
+        assert_eq!(
+            failed,
+            "Task 3 of webhook deploy failed with exit code 0 after 1.5 seconds"
+        );
+    }
+
+    #[test]
+    /// Room ids contain reserved characters, that need to be encoded
+    fn test_matrix_url() {
+        assert_eq!(
+            matrix_url("https://matrix.org/", "!room:matrix.org", "3-started"),
+            "https://matrix.org/_matrix/client/v3/rooms/%21room%3Amatrix%2Eorg/send/m.room.message/3%2Dstarted"
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use ::anyhow::{bail, Result};
+use ::log::info;
+use ::percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use ::serde_json::json;
+use ::std::time::Duration;
+
+use crate::notification::{render_summary, TaskSummary};
+use crate::settings::{CommitStatus, GitProvider, NotifyEvent};
+use crate::task::task::Commit;
+
+/// The time in seconds the API has to answer
+const REQUEST_TIMEOUT_SECONDS: u64 = 10;
+
+const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// Characters of repository names, that don't need to be encoded in the url
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.');
+
+/// Report the state of the task as status of the commit, that triggered it
+pub fn send_commit_status(
+    commit_status: &CommitStatus,
+    commit: &Commit,
+    summary: &TaskSummary,
+) -> Result<()> {
+    // The token must only be used for the configured repository
+    if !commit.is_valid() {
+        bail!("Invalid commit {}@{}", commit.repository, commit.sha);
+    }
+    if let Some(repository) = &commit_status.repository {
+        if !repository.eq_ignore_ascii_case(&commit.repository) {
+            bail!(
+                "Repository {} doesn't match the configured repository {}",
+                commit.repository,
+                repository
+            );
+        }
+    }
+
+    let url = get_status_url(commit_status, commit);
+    let (state, description) = get_state(summary);
+    let context = commit_status
+        .context
+        .clone()
+        .unwrap_or_else(|| format!("webhook-server/{}", summary.webhook));
+
+    let mut body = json!({
+        "state": state,
+        "description": description,
+        "context": context,
+    });
+    if let Some(template) = &commit_status.target_url {
+        body["target_url"] = render_summary(template, summary)?.into();
+    }
+
+    let authorization = match commit_status.provider {
+        GitProvider::Github => format!("Bearer {}", commit_status.token),
+        GitProvider::Gitea => format!("token {}", commit_status.token),
+    };
+
+    ureq::post(&url)
+        .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECONDS))
+        .set("Authorization", &authorization)
+        .set("Accept", "application/json")
+        .set("Content-Type", "application/json")
+        .send_string(&body.to_string())?;
+
+    info!(
+        webhook = summary.webhook.as_str(), task_id = summary.task_id;
+        "Reported commit status {} for {}@{}", state, commit.repository, commit.sha
+    );
+
+    Ok(())
+}
+
+/// The endpoint for statuses of a commit. GitHub and Gitea only differ in their base url.
+fn get_status_url(commit_status: &CommitStatus, commit: &Commit) -> String {
+    let base_url = match (commit_status.provider, &commit_status.base_url) {
+        (_, Some(base_url)) => base_url.trim_end_matches('/').to_string(),
+        (GitProvider::Github, None) => GITHUB_API_URL.to_string(),
+        (GitProvider::Gitea, None) => String::new(),
+    };
+    let api_prefix = match commit_status.provider {
+        GitProvider::Github => "",
+        GitProvider::Gitea => "/api/v1",
+    };
+
+    let (owner, repo) = commit
+        .repository
+        .split_once('/')
+        .unwrap_or((&commit.repository, ""));
+
+    format!(
+        "{}{}/repos/{}/{}/statuses/{}",
+        base_url,
+        api_prefix,
+        utf8_percent_encode(owner, PATH_SEGMENT),
+        utf8_percent_encode(repo, PATH_SEGMENT),
+        utf8_percent_encode(&commit.sha, PATH_SEGMENT)
+    )
+}
+
+/// Map the event of the task to a commit state and a description
+fn get_state(summary: &TaskSummary) -> (&'static str, String) {
+    let id = summary.task_id;
+    match summary.event {
+        NotifyEvent::Queued => ("pending", format!("Task {} is queued", id)),
+        NotifyEvent::Started => ("pending", format!("Task {} is running", id)),
+        NotifyEvent::Succeeded => ("success", format!("Task {} succeeded", id)),
+        NotifyEvent::Failed => (
+            "failure",
+            format!(
+                "Task {} failed with exit code {}",
+                id,
+                summary.exit_code.unwrap_or_default()
+            ),
+        ),
+        NotifyEvent::Cancelled => ("error", format!("Task {} has been cancelled", id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit_status(provider: GitProvider, base_url: Option<&str>) -> CommitStatus {
+        CommitStatus {
+            provider,
+            base_url: base_url.map(|url| url.to_string()),
+            token: "token".to_string(),
+            repository: None,
+            context: None,
+            target_url: None,
+        }
+    }
+
+    #[test]
+    /// GitHub defaults to its public API, Gitea needs the url of the instance
+    fn test_get_status_url() {
+        let commit = Commit {
+            repository: "owner/my.repo".to_string(),
+            sha: "abc".to_string(),
+        };
+
+        assert_eq!(
+            get_status_url(&commit_status(GitProvider::Github, None), &commit),
+            "https://api.github.com/repos/owner/my.repo/statuses/abc"
+        );
+        assert_eq!(
+            get_status_url(
+                &commit_status(GitProvider::Gitea, Some("https://git.example.org/")),
+                &commit
+            ),
+            "https://git.example.org/api/v1/repos/owner/my.repo/statuses/abc"
+        );
+    }
+
+    #[test]
+    /// Repositories and shas from the request can't point to other endpoints of the API
+    fn test_valid_commit() {
+        let sha = "a".repeat(40);
+        let commit = |repository: &str, sha: &str| Commit {
+            repository: repository.to_string(),
+            sha: sha.to_string(),
+        };
+
+        assert!(commit("owner/my-repo_1.0", &sha).is_valid());
+        assert!(commit("owner/repo", &"0123456789abcdef".repeat(4)).is_valid());
+        for (repository, sha) in [
+            ("owner/repo", "abc"),
+            ("owner/repo", &"g".repeat(40)),
+            ("owner", &sha),
+            ("owner/repo/extra", &sha),
+            ("owner/..", &sha),
+            ("../user", &sha),
+            ("owner/repo?x=1", &sha),
+        ] {
+            assert!(!commit(repository, sha).is_valid(), "{}", repository);
+        }
+    }
+
+    #[test]
+    /// Statuses are only sent for valid commits of the configured repository
+    fn test_pinned_repository() {
+        let mut commit_status = commit_status(GitProvider::Gitea, Some("http://127.0.0.1:1"));
+        commit_status.repository = Some("owner/repo".to_string());
+        let summary = TaskSummary {
+            webhook: "deploy".to_string(),
+            task_id: 1,
+            event: NotifyEvent::Started,
+            parameters: Default::default(),
+            attempt: 1,
+            exit_code: None,
+            started_at: None,
+            finished_at: None,
+            duration: None,
+            stdout: None,
+            stderr: None,
+            commit: None,
+        };
+        let commit = Commit {
+            repository: "other/repo".to_string(),
+            sha: "a".repeat(40),
+        };
+
+        let error = send_commit_status(&commit_status, &commit, &summary).unwrap_err();
+        assert!(error.to_string().contains("configured repository"));
+    }
+}
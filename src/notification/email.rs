@@ -0,0 +1,156 @@
+use ::anyhow::Result;
+use ::lettre::message::header::ContentType;
+use ::lettre::transport::smtp::authentication::Credentials;
+use ::lettre::{Message, SmtpTransport, Transport};
+use ::std::time::Duration;
+
+use crate::notification::{render_summary, TaskSummary};
+use crate::settings::{Email, Smtp};
+
+/// The time in seconds the mail server has to answer
+const SMTP_TIMEOUT_SECONDS: u64 = 10;
+
+const DEFAULT_SUBJECT: &str = "Task {{webhook}} - {{task_id}} failed with exit code {{exit_code}}";
+
+const DEFAULT_BODY: &str =
+    "Task {{task_id}} of webhook {{webhook}} failed with exit code {{exit_code}}.
+
+Attempt: {{attempt}}
+Started at: {{started_at}}
+Finished at: {{finished_at}}
+Duration: {{duration}} seconds
+
+Parameters:
+{{#each parameters}}
+    {{@key}}: {{this}}
+{{/each}}
+
+Stdout:
+{{stdout}}
+
+Stderr:
+{{stderr}}
+";
+
+/// Send an email with the summary of the task to all recipients
+pub fn send_email(smtp: &Smtp, email: &Email, summary: &TaskSummary) -> Result<()> {
+    let subject = render_summary(email.subject.as_deref().unwrap_or(DEFAULT_SUBJECT), summary)?;
+    let body = render_summary(email.body.as_deref().unwrap_or(DEFAULT_BODY), summary)?;
+
+    let mut builder = Message::builder()
+        .from(smtp.from.parse()?)
+        .subject(subject)
+        .header(ContentType::TEXT_PLAIN);
+    for recipient in email.to.iter() {
+        builder = builder.to(recipient.parse()?);
+    }
+    let message = builder.body(body)?;
+
+    let mut transport = if smtp.starttls {
+        SmtpTransport::starttls_relay(&smtp.host)?
+    } else {
+        SmtpTransport::builder_dangerous(&smtp.host)
+    };
+    transport = transport
+        .port(smtp.port)
+        .timeout(Some(Duration::from_secs(SMTP_TIMEOUT_SECONDS)));
+    if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+        transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+
+    transport.build().send(&message)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::NotifyEvent;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// A minimal SMTP sink, that accepts a single mail and returns its data
+    fn start_smtp_sink() -> (u16, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            writer.write_all(b"220 localhost ESMTP\r\n").unwrap();
+
+            let mut data = String::new();
+            let mut in_data = false;
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                if in_data {
+                    if line == ".\r\n" {
+                        in_data = false;
+                        writer.write_all(b"250 OK\r\n").unwrap();
+                    } else {
+                        data.push_str(&line);
+                    }
+                } else if line.starts_with("EHLO") {
+                    writer.write_all(b"250 localhost\r\n").unwrap();
+                } else if line.starts_with("DATA") {
+                    in_data = true;
+                    writer.write_all(b"354 Go ahead\r\n").unwrap();
+                } else if line.starts_with("QUIT") {
+                    writer.write_all(b"221 Bye\r\n").unwrap();
+                    break;
+                } else {
+                    writer.write_all(b"250 OK\r\n").unwrap();
+                }
+                line.clear();
+            }
+
+            data
+        });
+
+        (port, handle)
+    }
+
+    #[test]
+    /// The rendered mail is delivered to a local mail server
+    fn test_send_email() {
+        let (port, handle) = start_smtp_sink();
+        let smtp = Smtp {
+            host: "127.0.0.1".to_string(),
+            port,
+            starttls: false,
+            username: None,
+            password: None,
+            from: "webhook@localhost".to_string(),
+        };
+        let email = Email {
+            to: vec!["admin@localhost".to_string()],
+            subject: None,
+            body: None,
+        };
+        let summary = TaskSummary {
+            webhook: "deploy".to_string(),
+            task_id: 3,
+            event: NotifyEvent::Failed,
+            parameters: HashMap::new(),
+            attempt: 1,
+            exit_code: Some(2),
+            started_at: None,
+            finished_at: None,
+            duration: None,
+            stdout: Some("building".to_string()),
+            stderr: Some("no space left on device".to_string()),
+            commit: None,
+        };
+
+        send_email(&smtp, &email, &summary).unwrap();
+
+        let data = handle.join().unwrap();
+        assert!(data.contains("Subject: Task deploy - 3 failed with exit code 2"));
+        assert!(data.contains("To: admin@localhost"));
+        assert!(data.contains("no space left on device"));
+    }
+}
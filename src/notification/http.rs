@@ -0,0 +1,269 @@
+use ::anyhow::Result;
+use ::chrono::prelude::*;
+use ::hmac::{Hmac, Mac, NewMac};
+use ::log::{info, warn};
+use ::serde::Serialize;
+use ::sha2::Sha256;
+use ::std::time::Duration;
+
+use crate::audit::append_json_line;
+use crate::metrics::NOTIFICATIONS;
+use crate::notification::{chat, TaskSummary};
+use crate::settings::{Notify, NotifyEvent, NotifyKind, Settings};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The time in seconds a single delivery attempt may take
+const REQUEST_TIMEOUT_SECONDS: u64 = 10;
+
+/// The delay in seconds before the first retry. It's doubled for each following retry.
+const RETRY_BACKOFF_SECONDS: u64 = 1;
+
+/// The max delay in seconds between two attempts
+const MAX_RETRY_BACKOFF_SECONDS: u64 = 300;
+
+/// A single delivery attempt in the notification log
+#[derive(Serialize, Debug)]
+struct DeliveryEntry<'a> {
+    timestamp: DateTime<Local>,
+    webhook: &'a str,
+    task_id: i32,
+    event: NotifyEvent,
+    url: &'a str,
+    attempt: u32,
+    status: Option<u16>,
+    error: Option<String>,
+    delivered: bool,
+}
+
+/// Send the notification in the target's format.
+/// Returns the delay before the next attempt, if this attempt failed and `max_attempts` isn't reached yet.
+/// The delay starts at `RETRY_BACKOFF_SECONDS` and is doubled for each retry, up to `MAX_RETRY_BACKOFF_SECONDS`.
+pub fn deliver(
+    settings: &Settings,
+    target: &Notify,
+    summary: &TaskSummary,
+    transaction_id: &str,
+    attempt: u32,
+) -> Option<Duration> {
+    let (method, url, body) = match build_request(target, summary, transaction_id) {
+        Ok(request) => request,
+        Err(error) => {
+            warn!(
+                webhook = summary.webhook.as_str(), task_id = summary.task_id;
+                "Couldn't create notification for {}: {}", target.url, error
+            );
+            NOTIFICATIONS
+                .with_label_values(&[&summary.webhook, "failed"])
+                .inc();
+            return None;
+        }
+    };
+    let agent = ureq::AgentBuilder::new()
+        .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECONDS))
+        .build();
+
+    let mut request = agent
+        .request(method, &url)
+        .set("Content-Type", "application/json");
+    match target.kind {
+        NotifyKind::Http => {
+            request = request.set("X-Webhook-Event", event_name(summary.event));
+            if let Some(secret) = &target.secret {
+                request = request.set("X-Webhook-Signature", &generate_signature(secret, &body));
+            }
+        }
+        NotifyKind::Matrix => {
+            let token = target.access_token.as_deref().unwrap_or_default();
+            request = request.set("Authorization", &format!("Bearer {}", token));
+        }
+        NotifyKind::Slack | NotifyKind::Mattermost => {}
+    }
+
+    let (status, error) = match request.send_string(&body) {
+        Ok(response) => (Some(response.status()), None),
+        Err(ureq::Error::Status(status, _)) => (Some(status), None),
+        Err(error) => (None, Some(error.to_string())),
+    };
+    let delivered = matches!(status, Some(status) if (200..300).contains(&status));
+
+    if let Some(path) = &settings.notification_log {
+        let entry = DeliveryEntry {
+            timestamp: Local::now(),
+            webhook: &summary.webhook,
+            task_id: summary.task_id,
+            event: summary.event,
+            url: &url,
+            attempt,
+            status,
+            error: error.clone(),
+            delivered,
+        };
+        append_json_line(path, &entry);
+    }
+
+    if delivered {
+        info!(
+            webhook = summary.webhook.as_str(), task_id = summary.task_id;
+            "Delivered notification to {}", url
+        );
+        NOTIFICATIONS
+            .with_label_values(&[&summary.webhook, "delivered"])
+            .inc();
+        return None;
+    }
+
+    warn!(
+        webhook = summary.webhook.as_str(), task_id = summary.task_id;
+        "Notification to {} failed (attempt {} of {}): {}",
+        url,
+        attempt,
+        target.max_attempts,
+        error.unwrap_or_else(|| format!("Status {}", status.unwrap_or_default()))
+    );
+    if attempt >= target.max_attempts {
+        NOTIFICATIONS
+            .with_label_values(&[&summary.webhook, "failed"])
+            .inc();
+        return None;
+    }
+
+    Some(get_retry_delay(attempt))
+}
+
+/// Get the delay before the attempt, that follows the given one
+fn get_retry_delay(attempt: u32) -> Duration {
+    let factor = 2_u64.saturating_pow(attempt.saturating_sub(1));
+    let seconds = RETRY_BACKOFF_SECONDS
+        .saturating_mul(factor)
+        .min(MAX_RETRY_BACKOFF_SECONDS);
+
+    Duration::from_secs(seconds)
+}
+
+/// Create the id of a notification, that's the same for all of its attempts.
+/// Matrix uses it to drop duplicate messages of retries.
+pub fn new_transaction_id(summary: &TaskSummary, target_index: usize) -> String {
+    format!(
+        "{}-{}-{}-{}",
+        summary.task_id,
+        event_name(summary.event),
+        target_index,
+        Local::now().timestamp_millis()
+    )
+}
+
+/// Get the method, url and body of the request in the target's format
+fn build_request(
+    target: &Notify,
+    summary: &TaskSummary,
+    transaction_id: &str,
+) -> Result<(&'static str, String, String)> {
+    let request = match target.kind {
+        NotifyKind::Http => ("POST", target.url.clone(), serde_json::to_string(summary)?),
+        NotifyKind::Slack | NotifyKind::Mattermost => {
+            let message = chat::render_message(target, summary)?;
+            (
+                "POST",
+                target.url.clone(),
+                chat::incoming_webhook_body(&message),
+            )
+        }
+        NotifyKind::Matrix => {
+            let message = chat::render_message(target, summary)?;
+            let room_id = target.room_id.as_deref().unwrap_or_default();
+            let url = chat::matrix_url(&target.url, room_id, transaction_id);
+            ("PUT", url, chat::matrix_body(&message))
+        }
+    };
+
+    Ok(request)
+}
+
+fn event_name(event: NotifyEvent) -> &'static str {
+    match event {
+        NotifyEvent::Queued => "queued",
+        NotifyEvent::Started => "started",
+        NotifyEvent::Succeeded => "succeeded",
+        NotifyEvent::Failed => "failed",
+        NotifyEvent::Cancelled => "cancelled",
+    }
+}
+
+/// Create the `sha256=<hex>` HMAC signature of the body
+fn generate_signature(secret: &str, body: &str) -> String {
+    let mut hmac = HmacSha256::new_from_slice(secret.as_bytes())
+        .expect("Couldn't create hmac with current secret");
+    hmac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(hmac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    /// The signature can be checked with common HMAC implementations
+    fn test_generate_signature() {
+        // echo -n '{"task_id":1}' | openssl dgst -sha256 -hmac 'secret'
+        assert_eq!(
+            generate_signature("secret", r#"{"task_id":1}"#),
+            "sha256=e4ac6a5b1a5129307808af6a4b3ec935fab1fc5a17fb76ac191095f2ffc94b6a"
+        );
+    }
+
+    #[test]
+    /// The delay is doubled for each retry, but never exceeds the max backoff
+    fn test_retry_delay() {
+        assert_eq!(get_retry_delay(1), Duration::from_secs(1));
+        assert_eq!(get_retry_delay(2), Duration::from_secs(2));
+        assert_eq!(get_retry_delay(4), Duration::from_secs(8));
+        assert_eq!(
+            get_retry_delay(100),
+            Duration::from_secs(MAX_RETRY_BACKOFF_SECONDS)
+        );
+    }
+
+    #[test]
+    /// Retries of Matrix messages are sent to the same url, so the homeserver drops duplicates
+    fn test_matrix_retry_url() {
+        let target = Notify {
+            kind: NotifyKind::Matrix,
+            url: "https://matrix.org".to_string(),
+            secret: None,
+            template: None,
+            room_id: Some("!room:matrix.org".to_string()),
+            access_token: Some("token".to_string()),
+            events: Vec::new(),
+            max_attempts: 3,
+        };
+        let summary = TaskSummary {
+            webhook: "deploy".to_string(),
+            task_id: 3,
+            event: NotifyEvent::Started,
+            parameters: HashMap::new(),
+            attempt: 1,
+            exit_code: None,
+            started_at: None,
+            finished_at: None,
+            duration: None,
+            stdout: None,
+            stderr: None,
+            commit: None,
+        };
+        let transaction_id = new_transaction_id(&summary, 0);
+
+        let (_, first, _) = build_request(&target, &summary, &transaction_id).unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        let (_, retry, _) = build_request(&target, &summary, &transaction_id).unwrap();
+        assert_eq!(first, retry);
+        assert!(first.ends_with(&format!(
+            "/send/m.room.message/{}",
+            transaction_id.replace('-', "%2D")
+        )));
+
+        // Other targets of the same notification get their own id
+        assert_ne!(transaction_id, new_transaction_id(&summary, 1));
+    }
+}
@@ -0,0 +1,220 @@
+use ::actix::prelude::*;
+use ::actix_rt::ArbiterHandle;
+use ::chrono::prelude::*;
+use ::handlebars::{no_escape, Handlebars, RenderError};
+use ::log::{info, warn};
+use ::serde::Serialize;
+use ::std::collections::HashMap;
+
+use crate::messages::{RetryNotification, SendNotification};
+use crate::metrics::NOTIFICATIONS;
+use crate::settings::{Notify, NotifyEvent, Settings};
+use crate::task::task::{Commit, Task};
+
+mod chat;
+mod commit_status;
+mod email;
+mod http;
+
+/// The max amount of bytes of stdout and stderr, that's included in a notification
+const MAX_OUTPUT_BYTES: usize = 4096;
+
+/// The body of a notification
+#[derive(Serialize, Debug, Clone)]
+pub struct TaskSummary {
+    pub webhook: String,
+    pub task_id: i32,
+    pub event: NotifyEvent,
+    pub parameters: HashMap<String, String>,
+    pub attempt: u32,
+    pub exit_code: Option<u32>,
+    pub started_at: Option<DateTime<Local>>,
+    pub finished_at: Option<DateTime<Local>>,
+    pub duration: Option<f64>,
+    /// The tail of the task's output
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub commit: Option<Commit>,
+}
+
+impl TaskSummary {
+    pub fn new(task: &Task, event: NotifyEvent) -> Self {
+        TaskSummary {
+            webhook: task.webhook_name.clone(),
+            task_id: task.task_id,
+            event,
+            parameters: task.parameters.clone(),
+            attempt: task.attempt,
+            exit_code: task.exit_code,
+            started_at: task.started_at,
+            finished_at: task.finished_at,
+            duration: task.duration,
+            stdout: task.stdout.as_deref().map(tail),
+            stderr: task.stderr.as_deref().map(tail),
+            commit: task.commit.clone(),
+        }
+    }
+}
+
+/// The Actor that delivers notifications.
+/// It runs in a single thread, so notifications of a task are delivered in order.
+/// Only retries of failed deliveries may arrive after later notifications.
+pub struct Notifier {
+    pub settings: Settings,
+    /// Retries are scheduled on this arbiter, so the thread doesn't sleep until they're due
+    pub arbiter: ArbiterHandle,
+}
+
+impl Notifier {
+    /// Deliver the notification and schedule a retry, if the attempt failed
+    fn deliver(
+        &self,
+        target: &Notify,
+        summary: &TaskSummary,
+        transaction_id: String,
+        attempt: u32,
+        context: &mut SyncContext<Self>,
+    ) {
+        let delay = match http::deliver(&self.settings, target, summary, &transaction_id, attempt) {
+            Some(delay) => delay,
+            None => return,
+        };
+
+        let address = context.address();
+        let retry = RetryNotification {
+            summary: summary.clone(),
+            target: target.clone(),
+            transaction_id,
+            attempt: attempt + 1,
+        };
+        self.arbiter.spawn(async move {
+            actix::clock::sleep(delay).await;
+            address.do_send(retry);
+        });
+    }
+}
+
+impl Actor for Notifier {
+    type Context = SyncContext<Self>;
+}
+
+impl Handler<SendNotification> for Notifier {
+    type Result = ();
+
+    fn handle(&mut self, message: SendNotification, context: &mut Self::Context) {
+        for (index, target) in message.targets.iter().enumerate() {
+            if !target.events.contains(&message.event) {
+                continue;
+            }
+            let transaction_id = http::new_transaction_id(&message.summary, index);
+            self.deliver(target, &message.summary, transaction_id, 1, context);
+        }
+
+        if let (Some(commit_status), Some(commit)) =
+            (&message.commit_status, &message.summary.commit)
+        {
+            let summary = &message.summary;
+            let outcome = match commit_status::send_commit_status(commit_status, commit, summary) {
+                Ok(()) => "delivered",
+                Err(error) => {
+                    warn!(
+                        webhook = summary.webhook.as_str(), task_id = summary.task_id;
+                        "Couldn't report commit status of {}@{}: {}",
+                        commit.repository, commit.sha, error
+                    );
+                    "failed"
+                }
+            };
+            NOTIFICATIONS
+                .with_label_values(&[&summary.webhook, outcome])
+                .inc();
+        }
+
+        // Emails are only sent for failed tasks
+        if let (Some(smtp), Some(email)) = (&self.settings.smtp, &message.email) {
+            if message.event == NotifyEvent::Failed {
+                let summary = &message.summary;
+                let outcome = match email::send_email(smtp, email, summary) {
+                    Ok(()) => {
+                        info!(
+                            webhook = summary.webhook.as_str(), task_id = summary.task_id;
+                            "Sent failure email to {}", email.to.join(", ")
+                        );
+                        "delivered"
+                    }
+                    Err(error) => {
+                        warn!(
+                            webhook = summary.webhook.as_str(), task_id = summary.task_id;
+                            "Couldn't send failure email: {}", error
+                        );
+                        "failed"
+                    }
+                };
+                NOTIFICATIONS
+                    .with_label_values(&[&summary.webhook, outcome])
+                    .inc();
+            }
+        }
+    }
+}
+
+impl Handler<RetryNotification> for Notifier {
+    type Result = ();
+
+    fn handle(&mut self, message: RetryNotification, context: &mut Self::Context) {
+        self.deliver(
+            &message.target,
+            &message.summary,
+            message.transaction_id,
+            message.attempt,
+            context,
+        );
+    }
+}
+
+/// Render a notification template with the summary of a task.
+/// Missing values are rendered as empty strings and nothing is escaped.
+pub fn render_summary(template: &str, summary: &TaskSummary) -> Result<String, RenderError> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(no_escape);
+
+    handlebars.render_template(template, summary)
+}
+
+/// Get the event of a task, that just finished
+pub fn get_finished_event(task: &Task) -> NotifyEvent {
+    if task.exit_code == Some(0) {
+        NotifyEvent::Succeeded
+    } else {
+        NotifyEvent::Failed
+    }
+}
+
+/// Keep the last `MAX_OUTPUT_BYTES` of the output
+fn tail(output: &str) -> String {
+    if output.len() <= MAX_OUTPUT_BYTES {
+        return output.to_string();
+    }
+
+    let mut start = output.len() - MAX_OUTPUT_BYTES;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    output[start..].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Long output is cut at the front without splitting characters
+    fn test_tail() {
+        assert_eq!(tail("short"), "short");
+
+        let output = format!("ä{}", "a".repeat(MAX_OUTPUT_BYTES - 1));
+        let tail = tail(&output);
+        assert_eq!(tail.len(), MAX_OUTPUT_BYTES - 1);
+        assert!(tail.chars().all(|character| character == 'a'));
+    }
+}
@@ -0,0 +1,68 @@
+use ::anyhow::{anyhow, Result};
+use ::argon2::password_hash::rand_core::OsRng;
+use ::argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use ::argon2::Argon2;
+use ::log::warn;
+
+/// Hash a password with argon2id and a random salt
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|error| anyhow!("Couldn't hash password: {}", error))?;
+
+    Ok(hash.to_string())
+}
+
+/// Check a password against an argon2 or bcrypt hash.
+/// Both libraries compare the hashes in constant time.
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    if is_bcrypt_hash(hash) {
+        return bcrypt::verify(password, hash).unwrap_or_else(|error| {
+            warn!("Couldn't verify bcrypt hash: {}", error);
+            false
+        });
+    }
+
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(error) => {
+            warn!("Couldn't parse argon2 hash: {}", error);
+            false
+        }
+    }
+}
+
+/// Check that the hash has a supported format
+pub fn is_supported_hash(hash: &str) -> bool {
+    is_bcrypt_hash(hash) || (hash.starts_with("$argon2") && PasswordHash::new(hash).is_ok())
+}
+
+fn is_bcrypt_hash(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| hash.starts_with(prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Argon2 hashes are created and both formats are verified
+    fn test_verify_password() {
+        let hash = hash_password("hunter2").unwrap();
+        assert!(is_supported_hash(&hash));
+        assert!(verify_password(&hash, "hunter2"));
+        assert!(!verify_password(&hash, "hunter3"));
+
+        let hash = bcrypt::hash("hunter2", 4).unwrap();
+        assert!(is_supported_hash(&hash));
+        assert!(verify_password(&hash, "hunter2"));
+        assert!(!verify_password(&hash, "hunter3"));
+
+        assert!(!is_supported_hash("hunter2"));
+    }
+}
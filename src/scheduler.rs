@@ -1,16 +1,26 @@
 use ::actix::prelude::*;
-use ::log::info;
+use ::anyhow::{anyhow, Result};
+use ::chrono::prelude::*;
+use ::chrono::Duration;
+use ::log::{info, warn};
+use ::std::collections::HashMap;
 
 use crate::messages::*;
-use crate::settings::Settings;
+use crate::notification::{get_finished_event, Notifier, TaskSummary};
+use crate::settings::{FollowUp, NotifyEvent, Settings};
 use crate::task::executor::TaskExecutor;
 use crate::task::queue::TaskQueue;
+use crate::task::step::render_steps;
+use crate::task::task::Task;
+use crate::task::template::render_template;
 
 pub struct Scheduler {
     pub task_executor: Addr<TaskExecutor>,
+    pub notifier: Addr<Notifier>,
     pub own_addr: Option<Addr<Self>>,
     pub settings: Settings,
-    pub current_workers: i32,
+    /// The last time a task has been dispatched or finished
+    last_progress: DateTime<Local>,
     task_queue: TaskQueue,
 }
 
@@ -19,6 +29,13 @@ impl Actor for Scheduler {
 
     fn started(&mut self, context: &mut Self::Context) {
         self.own_addr = Some(context.address());
+
+        // Regularly check for scheduled tasks, that are due
+        context.run_interval(std::time::Duration::from_secs(1), |scheduler, _context| {
+            scheduler.task_queue.queue_due_tasks();
+            scheduler.dispatch_tasks();
+        });
+
         info!("Queue management actor started up");
     }
 }
@@ -27,21 +44,87 @@ impl Handler<GetQueue> for Scheduler {
     type Result = String;
 
     /// Handle a NewTask. Check whether the task can be dispatch directly
-    fn handle(&mut self, _message: GetQueue, _context: &mut Self::Context) -> String {
-        match serde_json::to_string(&self.task_queue) {
+    fn handle(&mut self, message: GetQueue, _context: &mut Self::Context) -> String {
+        let json = match &message.webhooks {
+            Some(webhooks) => serde_json::to_string(&self.task_queue.filter_webhooks(webhooks)),
+            None => serde_json::to_string(&self.task_queue),
+        };
+        match json {
             Ok(json) => json,
             Err(error) => format!("Got error while encoding json: {:?}", error),
         }
     }
 }
 
+impl Handler<GetStatistics> for Scheduler {
+    type Result = String;
+
+    fn handle(&mut self, message: GetStatistics, _context: &mut Self::Context) -> String {
+        let statistics = self.task_queue.get_statistics(message.webhooks.as_deref());
+        match serde_json::to_string(&statistics) {
+            Ok(json) => json,
+            Err(error) => format!("Got error while encoding json: {:?}", error),
+        }
+    }
+}
+
+impl Handler<GetTasks> for Scheduler {
+    type Result = TaskOverview;
+
+    fn handle(&mut self, message: GetTasks, _context: &mut Self::Context) -> TaskOverview {
+        self.task_queue
+            .get_overview(message.finished_limit, message.webhooks.as_deref())
+    }
+}
+
+impl Handler<GetTask> for Scheduler {
+    type Result = Option<Task>;
+
+    fn handle(&mut self, message: GetTask, _context: &mut Self::Context) -> Option<Task> {
+        self.task_queue.get_task(message.task_id)
+    }
+}
+
+impl Handler<Ping> for Scheduler {
+    type Result = bool;
+
+    /// Handle a Ping. The worker pool counts as stalled, if all workers are busy, tasks are
+    /// waiting and nothing happened for longer than `ready_stall_timeout` seconds.
+    fn handle(&mut self, _message: Ping, _context: &mut Self::Context) -> bool {
+        let stall_timeout = Duration::seconds(self.settings.ready_stall_timeout);
+        !(self.task_queue.is_saturated() && Local::now() - self.last_progress > stall_timeout)
+    }
+}
+
 impl Handler<NewTask> for Scheduler {
-    type Result = ();
+    type Result = NewTaskResult;
 
     /// Handle a NewTask. Check whether the task can be dispatch directly
-    fn handle(&mut self, new_task: NewTask, _context: &mut Self::Context) {
-        self.task_queue.add_task(new_task);
+    fn handle(&mut self, new_task: NewTask, _context: &mut Self::Context) -> NewTaskResult {
+        let result = self.task_queue.add_task(new_task);
+        if let NewTaskResult::Added(task_id) = result {
+            self.notify_queued(task_id);
+        }
         self.dispatch_tasks();
+
+        result
+    }
+}
+
+impl Handler<CancelTask> for Scheduler {
+    type Result = bool;
+
+    /// Handle a CancelTask. Only tasks that haven't been started yet can be cancelled
+    fn handle(&mut self, message: CancelTask, _context: &mut Self::Context) -> bool {
+        match self.task_queue.cancel_task(message.task_id) {
+            Some(task) => {
+                info!("Cancelled task: {}", message.task_id);
+                self.notify(NotifyEvent::Cancelled, &task);
+                self.task_queue.update_metrics();
+                true
+            }
+            None => false,
+        }
     }
 }
 
@@ -53,41 +136,186 @@ impl Handler<TaskCompleted> for Scheduler {
     /// Also check for new tasks to dispatch
     fn handle(&mut self, message: TaskCompleted, _context: &mut Self::Context) {
         info!(
-            "Finished task: {} - {}",
-            message.webhook_name, message.task_id
+            webhook = message.webhook_name.as_str(), task_id = message.task_id;
+            "Finished task: {} - {}", message.webhook_name, message.task_id
         );
-        self.task_queue.finish_task(message);
+        let task = self.task_queue.finish_task(message);
+        self.last_progress = Local::now();
+        self.notify(get_finished_event(&task), &task);
+        // Follow-ups are only triggered, once there are no retries left
+        if !self.retry_task(&task) {
+            self.trigger_follow_ups(&task);
+        }
         self.dispatch_tasks();
     }
 }
 
 impl Scheduler {
     /// Create a new Scheduler
-    pub fn new(task_executor: Addr<TaskExecutor>, settings: Settings) -> Self {
+    pub fn new(
+        task_executor: Addr<TaskExecutor>,
+        notifier: Addr<Notifier>,
+        settings: Settings,
+    ) -> Self {
         Scheduler {
             task_executor: task_executor.clone(),
+            notifier,
             own_addr: None,
             settings: settings.clone(),
-            current_workers: 0,
+            last_progress: Local::now(),
             task_queue: TaskQueue::new(settings),
         }
     }
 
+    /// Schedule another attempt of a failed task, if the webhook's retry settings allow it.
+    /// Returns whether a retry has been scheduled.
+    fn retry_task(&mut self, task: &Task) -> bool {
+        let webhook = self
+            .settings
+            .get_webhook_by_name(&task.webhook_name)
+            .unwrap();
+        let retry = match webhook.retry {
+            Some(retry) => retry,
+            None => return false,
+        };
+
+        let exit_code = task.exit_code.unwrap_or(1);
+        if let Some(delay) = retry.get_retry_delay(exit_code, task.attempt) {
+            let retry_id = self.task_queue.retry_task(task, Local::now() + delay);
+            self.notify_queued(retry_id);
+            info!(
+                webhook = task.webhook_name.as_str(), task_id = task.task_id;
+                "Retrying task: {} - {} as {} (attempt {} of {})",
+                task.webhook_name,
+                task.task_id,
+                retry_id,
+                task.attempt + 1,
+                retry.max_attempts
+            );
+            return true;
+        }
+
+        false
+    }
+
+    /// Enqueue the `on_success` or `on_failure` webhooks of a finished task
+    fn trigger_follow_ups(&mut self, task: &Task) {
+        let webhook = self
+            .settings
+            .get_webhook_by_name(&task.webhook_name)
+            .unwrap();
+        let follow_ups = if task.exit_code == Some(0) {
+            webhook.on_success
+        } else {
+            webhook.on_failure
+        };
+
+        for follow_up in follow_ups {
+            let new_task = match get_follow_up_task(&self.settings, &follow_up, task) {
+                Ok(new_task) => new_task,
+                Err(error) => {
+                    warn!(
+                        "Couldn't create follow-up {} of task {} - {}: {}",
+                        follow_up.webhook, task.webhook_name, task.task_id, error
+                    );
+                    continue;
+                }
+            };
+
+            let result = self.task_queue.add_task(new_task);
+            if let NewTaskResult::Added(task_id) = result {
+                self.notify_queued(task_id);
+            }
+            info!(
+                "Triggered follow-up {} of task {} - {}: {:?}",
+                follow_up.webhook, task.webhook_name, task.task_id, result
+            );
+        }
+    }
+
+    /// Send a notification to the webhook's notify targets, if it has any
+    fn notify(&self, event: NotifyEvent, task: &Task) {
+        let webhook = self
+            .settings
+            .get_webhook_by_name(&task.webhook_name)
+            .unwrap();
+        if webhook.notify.is_empty() && webhook.email.is_none() && webhook.commit_status.is_none() {
+            return;
+        }
+
+        self.notifier.do_send(SendNotification {
+            event,
+            summary: TaskSummary::new(task, event),
+            targets: webhook.notify,
+            email: webhook.email,
+            commit_status: webhook.commit_status,
+        });
+    }
+
+    /// Send the notifications of a task, that has just been added
+    fn notify_queued(&self, task_id: i32) {
+        if let Some(task) = self.task_queue.get_pending_task(task_id) {
+            self.notify(NotifyEvent::Queued, task);
+        }
+    }
+
     /// Check wheter new tasks from the queue can be dispatched
     fn dispatch_tasks(&mut self) {
         let tasks = self.task_queue.get_tasks_for_dispatch();
 
+        if !tasks.is_empty() {
+            self.last_progress = Local::now();
+        }
+
         for task in tasks {
+            self.notify(NotifyEvent::Started, &task);
             let addr = self.own_addr.as_ref().unwrap().clone();
             let message = StartTask {
                 webhook_name: task.webhook_name,
                 task_id: task.task_id,
                 command: task.command,
                 cwd: task.cwd,
+                steps: task.steps,
+                log_file: task.log_file,
                 scheduler: addr,
             };
 
             self.task_executor.do_send(message);
         }
+
+        self.task_queue.update_metrics();
     }
 }
+
+/// Build the NewTask of a follow-up webhook.
+/// The parameter templates of the follow-up are rendered with the parameters of the parent task.
+fn get_follow_up_task(settings: &Settings, follow_up: &FollowUp, parent: &Task) -> Result<NewTask> {
+    let parameters = if follow_up.parameters.is_empty() {
+        parent.parameters.clone()
+    } else {
+        let mut parameters = HashMap::new();
+        for (name, template) in follow_up.parameters.iter() {
+            let value = render_template(template, &parent.parameters)?;
+            parameters.insert(name.clone(), value);
+        }
+        parameters
+    };
+
+    let webhook = settings
+        .get_webhook_by_name(&follow_up.webhook)
+        .map_err(|_| anyhow!("Unknown webhook"))?;
+    let command = render_template(&webhook.command, &parameters)?;
+    let steps = render_steps(&webhook.steps, &webhook.cwd, &parameters)?;
+
+    Ok(NewTask {
+        webhook_name: webhook.name,
+        parameters,
+        cwd: webhook.cwd,
+        command,
+        steps,
+        added_at: Local::now(),
+        run_at: None,
+        parent_id: Some(parent.task_id),
+        commit: None,
+    })
+}
@@ -0,0 +1,154 @@
+use ::anyhow::{anyhow, Result};
+use ::config::{Config, Value};
+use ::log::warn;
+use ::serde::de::IgnoredAny;
+use ::std::collections::HashMap;
+use ::std::path::Path;
+
+/// Settings that contain secrets.
+/// Each of them can be read from a file via `<key>_file` or from the environment via `<key>_env`.
+const SECRET_KEYS: [&str; 5] = [
+    "secret",
+    "basic_auth_password",
+    "password",
+    "access_token",
+    "token",
+];
+
+/// Replace all `<key>_file` and `<key>_env` indirections of secrets with their values
+pub fn resolve_secrets(value: Value) -> Result<Value> {
+    if let Ok(array) = value.clone().into_array() {
+        let array = array
+            .into_iter()
+            .map(resolve_secrets)
+            .collect::<Result<Vec<_>>>()?;
+        return Ok(Value::new(None, array));
+    }
+    let mut table = match value.clone().into_table() {
+        Ok(table) => table,
+        Err(_) => return Ok(value),
+    };
+
+    for key in SECRET_KEYS {
+        let file = table.remove(&format!("{}_file", key));
+        let env = table.remove(&format!("{}_env", key));
+        let inline = table.get(key).is_some_and(|value| !is_nil(value));
+
+        let secret = match (file, env) {
+            (None, None) => continue,
+            (Some(path), None) if !inline => {
+                let path = path.into_str()?;
+                let secret = std::fs::read_to_string(&path)
+                    .map_err(|error| anyhow!("Couldn't read {}: {}", path, error))?;
+                secret.trim_end_matches(&['\r', '\n'][..]).to_string()
+            }
+            (None, Some(name)) if !inline => {
+                let name = name.into_str()?;
+                std::env::var(&name).map_err(|_| {
+                    anyhow!("Couldn't read environment variable {} of {}", name, key)
+                })?
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Only one of {}, {}_file and {}_env may be set",
+                    key,
+                    key,
+                    key
+                ))
+            }
+        };
+        table.insert(key.to_string(), Value::new(None, secret));
+    }
+
+    let table = table
+        .into_iter()
+        .map(|(key, value)| Ok((key, resolve_secrets(value)?)))
+        .collect::<Result<HashMap<_, _>>>()?;
+
+    Ok(Value::new(None, table))
+}
+
+/// Warn about config files with inline secrets, that can be read by other users
+pub fn warn_about_inline_secrets(paths: &[impl AsRef<Path>]) {
+    for path in paths.iter().map(AsRef::as_ref) {
+        if !path.is_file() || !is_readable_by_others(path) {
+            continue;
+        }
+
+        let config = Config::default().with_merged(config::File::from(path));
+        if config.is_ok_and(|config| contains_inline_secret(&config.cache)) {
+            warn!(
+                "The config file {:?} contains secrets, but is readable by other users. \
+                Restrict its permissions or use *_file or *_env settings instead.",
+                path
+            );
+        }
+    }
+}
+
+fn contains_inline_secret(value: &Value) -> bool {
+    if let Ok(array) = value.clone().into_array() {
+        return array.iter().any(contains_inline_secret);
+    }
+
+    value.clone().into_table().is_ok_and(|table| {
+        table.iter().any(|(key, value)| {
+            (SECRET_KEYS.contains(&key.as_str()) && !is_nil(value)) || contains_inline_secret(value)
+        })
+    })
+}
+
+fn is_nil(value: &Value) -> bool {
+    matches!(value.clone().try_into::<Option<IgnoredAny>>(), Ok(None))
+}
+
+#[cfg(unix)]
+fn is_readable_by_others(path: &Path) -> bool {
+    use ::std::os::unix::fs::PermissionsExt;
+
+    std::fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o044 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_readable_by_others(_path: &Path) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Secrets are read from files and the environment, also within lists
+    fn test_resolve_secrets() {
+        let path = std::env::temp_dir().join(format!("secret_test_{}", std::process::id()));
+        std::fs::write(&path, "from file\n").unwrap();
+        std::env::set_var("SECRET_TEST_TOKEN", "from env");
+
+        let yaml = format!(
+            "secret_file: {:?}\nwebhooks:\n  - token_env: SECRET_TEST_TOKEN\n",
+            path
+        );
+        let config = Config::default()
+            .with_merged(config::File::from_str(&yaml, config::FileFormat::Yaml))
+            .unwrap();
+        let value = resolve_secrets(config.cache).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let table = value.clone().into_table().unwrap();
+        assert_eq!(table["secret"].clone().into_str().unwrap(), "from file");
+        assert!(!table.contains_key("secret_file"));
+        let webhook = table["webhooks"].clone().into_array().unwrap()[0]
+            .clone()
+            .into_table()
+            .unwrap();
+        assert_eq!(webhook["token"].clone().into_str().unwrap(), "from env");
+        assert!(contains_inline_secret(&value));
+
+        // Inline secrets can't be combined with an indirection
+        let mut config = Config::default();
+        config.set("secret", "inline").unwrap();
+        config.set("secret_env", "SECRET_TEST_TOKEN").unwrap();
+        assert!(resolve_secrets(config.cache).is_err());
+    }
+}
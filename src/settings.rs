@@ -1,21 +1,390 @@
 use ::actix_web::http::StatusCode;
 use ::actix_web::HttpResponse;
 use ::anyhow::{anyhow, Result};
+use ::chrono::{DateTime, Duration, Local};
 use ::config::ConfigError;
 use ::config::*;
+use ::ipnet::IpNet;
 use ::log::{info, warn};
-use ::serde::Deserialize;
+use ::serde::de::Error as _;
+use ::serde::{Deserialize, Deserializer, Serialize};
+use ::std::collections::HashMap;
+use ::std::net::IpAddr;
 use ::std::path::{Path, PathBuf};
 
+use crate::jwt::{load_keys, JwtKey};
+use crate::password::is_supported_hash;
+use crate::secrets::resolve_secrets;
+
+/// Names of internal routes, that can't be used as webhook names
+const RESERVED_NAMES: [&str; 3] = ["dashboard", "metrics", "stats"];
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct Webhook {
     pub name: String,
+    #[serde(default)]
     pub command: String,
     pub cwd: String,
     #[serde(default = "webhook_mode_default")]
     pub mode: String,
+    /// The platform that sends the requests. Determines how the signature is checked.
+    #[serde(default)]
+    pub provider: Provider,
+    /// Only clients from these networks may trigger the webhook
+    #[serde(default, deserialize_with = "deserialize_networks")]
+    pub allowed_ips: Vec<IpNet>,
+    /// Claims, that tokens of `jwt_issuers` need to trigger the webhook.
+    /// Tokens can't trigger the webhook, if it's empty.
+    #[serde(default)]
+    pub jwt_claims: HashMap<String, String>,
+    /// Require a valid signature in addition to a client certificate
+    #[serde(default)]
+    pub client_certificate_and_secret: bool,
+    /// Limit the amount of accepted requests
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
     #[serde(default = "webhook_parallel_default")]
     pub parallel_processes: i32,
+    #[serde(default)]
+    pub max_queued: Option<usize>,
+    #[serde(default)]
+    pub retry: Option<Retry>,
+    #[serde(default)]
+    pub on_success: Vec<FollowUp>,
+    #[serde(default)]
+    pub on_failure: Vec<FollowUp>,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+    #[serde(default)]
+    pub notify: Vec<Notify>,
+    #[serde(default)]
+    pub email: Option<Email>,
+    #[serde(default)]
+    pub commit_status: Option<CommitStatus>,
+}
+
+/// A single step of a webhook's pipeline. Steps are executed one after another.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Step {
+    pub name: Option<String>,
+    pub command: String,
+    /// Defaults to the cwd of the webhook
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Timeout in seconds, after which the step is killed
+    pub timeout: Option<u64>,
+    /// Continue with the next step, even if this one fails
+    #[serde(default)]
+    pub continue_on_error: bool,
+}
+
+/// Another webhook, that's triggered after a task finished
+#[derive(Debug, Deserialize, Clone)]
+pub struct FollowUp {
+    pub webhook: String,
+    /// Templates for the parameters of the follow-up, rendered with the parameters of the
+    /// finished task. If empty, all parameters of the finished task are passed through.
+    #[serde(default)]
+    pub parameters: HashMap<String, String>,
+}
+
+/// The platform, that sends the requests of a webhook
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Provider {
+    /// `Signature` or `X-Hub-Signature` header with a `sha1=` prefixed HMAC-SHA1
+    #[default]
+    Generic,
+    /// `X-Hub-Signature-256` with HMAC-SHA256 or `X-Hub-Signature` with HMAC-SHA1
+    Github,
+    /// The secret itself in the `X-Gitlab-Token` header
+    Gitlab,
+    /// `X-Gitea-Signature` or `X-Gogs-Signature` with an unprefixed HMAC-SHA256
+    Gitea,
+    /// `X-Hub-Signature` header with a `sha256=` prefixed HMAC-SHA256
+    Bitbucket,
+    /// `X-Webhook-Signature` with a `sha256=` prefixed HMAC-SHA256 of the
+    /// `X-Webhook-Timestamp` header, a dot and the body
+    Timestamped,
+}
+
+/// The state changes of a task, that trigger notifications
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyEvent {
+    Queued,
+    Started,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// The format of a notification
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyKind {
+    /// The json summary of the task
+    #[default]
+    Http,
+    /// A message for an incoming webhook of Slack
+    Slack,
+    /// A message for an incoming webhook of Mattermost
+    Mattermost,
+    /// A message, that's sent to a Matrix room via the client-server API
+    Matrix,
+}
+
+/// A target, that's notified about the state changes of a webhook's tasks
+#[derive(Debug, Deserialize, Clone)]
+pub struct Notify {
+    #[serde(default)]
+    pub kind: NotifyKind,
+    /// The url of the target. For Matrix, this is the homeserver's base url.
+    pub url: String,
+    /// The key for the HMAC signature of the notification's body
+    pub secret: Option<String>,
+    /// Handlebars template of chat messages, rendered with the summary of the task
+    pub template: Option<String>,
+    /// The room and the access token of the Matrix user, that sends the messages
+    pub room_id: Option<String>,
+    pub access_token: Option<String>,
+    #[serde(default = "notify_events_default")]
+    pub events: Vec<NotifyEvent>,
+    /// The max amount of delivery attempts, including the first one
+    #[serde(default = "notify_max_attempts_default")]
+    pub max_attempts: u32,
+}
+
+fn notify_events_default() -> Vec<NotifyEvent> {
+    vec![
+        NotifyEvent::Started,
+        NotifyEvent::Succeeded,
+        NotifyEvent::Failed,
+    ]
+}
+
+fn notify_max_attempts_default() -> u32 {
+    3
+}
+
+/// The API flavour of a git forge
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum GitProvider {
+    Github,
+    Gitea,
+}
+
+/// Report the state of tasks as status of the commit, that triggered them
+#[derive(Debug, Deserialize, Clone)]
+pub struct CommitStatus {
+    pub provider: GitProvider,
+    /// The base url of the API. Defaults to `https://api.github.com` for GitHub.
+    /// For Gitea, this is the url of the instance.
+    pub base_url: Option<String>,
+    pub token: String,
+    /// The `owner/repo`, whose commits get a status. Other repositories are ignored.
+    #[serde(default)]
+    pub repository: Option<String>,
+    /// The name of the status. Defaults to `webhook-server/<webhook>`.
+    pub context: Option<String>,
+    /// A handlebars template for the link of the status, rendered with the summary of the task
+    pub target_url: Option<String>,
+}
+
+/// A user for the basic auth, whose password is stored as argon2 or bcrypt hash
+#[derive(Debug, Deserialize, Clone)]
+pub struct BasicAuthUser {
+    pub name: String,
+    pub password_hash: String,
+    /// The webhooks, the user may access. All webhooks, if it's empty.
+    #[serde(default)]
+    pub webhooks: Vec<String>,
+}
+
+/// Maps verified client certificates to a principal
+#[derive(Debug, Deserialize, Clone)]
+pub struct ClientCertificate {
+    pub name: String,
+    /// The common name of the certificate's subject
+    #[serde(default)]
+    pub common_name: Option<String>,
+    /// One of the DNS names, emails or URIs of the subject alternative names
+    #[serde(default)]
+    pub san: Option<String>,
+    /// The webhooks, the certificate may access. All webhooks, if it's empty.
+    #[serde(default)]
+    pub webhooks: Vec<String>,
+}
+
+/// The permissions of an API token
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Trigger new tasks and re-run existing ones
+    Trigger,
+    /// Read the queue, statistics, metrics and the dashboard
+    Read,
+    /// Cancel scheduled or queued tasks
+    Cancel,
+    /// All of the above
+    Admin,
+}
+
+/// A named token, that's sent as `Authorization: Bearer <token>`
+#[derive(Debug, Deserialize, Clone)]
+pub struct ApiToken {
+    pub name: String,
+    /// The hex encoded SHA256 hash of the token
+    pub hash: String,
+    /// The webhooks, the token may be used for. All webhooks, if it's empty.
+    #[serde(default)]
+    pub webhooks: Vec<String>,
+    pub scopes: Vec<Scope>,
+}
+
+impl ApiToken {
+    /// Check whether the token grants the scope for the webhook.
+    /// Requests, that don't concern a single webhook, only need the scope.
+    pub fn allows(&self, scope: Scope, webhook: Option<&str>) -> bool {
+        let has_scope = self.scopes.contains(&Scope::Admin) || self.scopes.contains(&scope);
+        let has_webhook = match webhook {
+            Some(webhook) => {
+                self.webhooks.is_empty() || self.webhooks.iter().any(|name| name == webhook)
+            }
+            None => true,
+        };
+
+        has_scope && has_webhook
+    }
+}
+
+/// One of several secrets, that are valid at the same time to allow rotating them
+#[derive(Debug, Deserialize, Clone)]
+pub struct Secret {
+    /// Logged when a signature matches the secret, to track which senders still use it
+    pub id: String,
+    pub secret: String,
+    /// The secret isn't accepted anymore after this time
+    #[serde(default)]
+    pub expires: Option<DateTime<Local>>,
+}
+
+/// An issuer of OIDC tokens, e.g. GitHub Actions or GitLab CI.
+/// Its tokens are sent as `Authorization: Bearer <token>`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct JwtIssuer {
+    /// The `iss` claim of the tokens
+    pub issuer: String,
+    /// The `aud` claim, the tokens need to be created for
+    pub audience: String,
+    /// A JSON Web Key Set with the public keys of the issuer
+    pub jwks_file: Option<PathBuf>,
+    /// PEM encoded public keys of the issuer
+    #[serde(default)]
+    pub public_keys: Vec<PathBuf>,
+    /// Claims, that all tokens of the issuer need to have
+    #[serde(default)]
+    pub claims: HashMap<String, String>,
+    #[serde(default = "jwt_scopes_default")]
+    pub scopes: Vec<Scope>,
+    /// Loaded from the `jwks_file` and `public_keys` after parsing the settings
+    #[serde(skip)]
+    pub keys: Vec<JwtKey>,
+}
+
+fn jwt_scopes_default() -> Vec<Scope> {
+    vec![Scope::Trigger]
+}
+
+/// The mail server, that's used to send email notifications
+#[derive(Debug, Deserialize, Clone)]
+pub struct Smtp {
+    pub host: String,
+    #[serde(default = "smtp_port_default")]
+    pub port: u16,
+    /// Upgrade the connection via STARTTLS. Only disable this for local mail servers.
+    #[serde(default = "smtp_starttls_default")]
+    pub starttls: bool,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+}
+
+fn smtp_port_default() -> u16 {
+    587
+}
+
+fn smtp_starttls_default() -> bool {
+    true
+}
+
+/// Email notifications about failed tasks
+#[derive(Debug, Deserialize, Clone)]
+pub struct Email {
+    pub to: Vec<String>,
+    /// Handlebars templates, rendered with the summary of the task
+    pub subject: Option<String>,
+    pub body: Option<String>,
+}
+
+/// Settings for automatic retries of failed tasks
+#[derive(Debug, Deserialize, Clone)]
+pub struct Retry {
+    /// The max amount of attempts, including the first one
+    pub max_attempts: u32,
+    /// The delay before the first retry. It's doubled for each following retry.
+    #[serde(default = "retry_backoff_default")]
+    pub backoff_seconds: i64,
+    /// Only retry on these exit codes. All non-zero exit codes are retried, if this is empty.
+    #[serde(default)]
+    pub exit_codes: Vec<u32>,
+}
+
+impl Retry {
+    /// Get the delay for the next attempt, if a task with this exit code should be retried
+    pub fn get_retry_delay(&self, exit_code: u32, attempt: u32) -> Option<Duration> {
+        if exit_code == 0 || attempt >= self.max_attempts {
+            return None;
+        }
+
+        if !self.exit_codes.is_empty() && !self.exit_codes.contains(&exit_code) {
+            return None;
+        }
+
+        let factor = 2_i64.saturating_pow(attempt - 1);
+        Some(Duration::seconds(
+            self.backoff_seconds.saturating_mul(factor),
+        ))
+    }
+}
+
+fn retry_backoff_default() -> i64 {
+    10
+}
+
+/// Accept at most `requests` requests every `seconds` seconds
+#[derive(Debug, Deserialize, Clone)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub seconds: i64,
+}
+
+/// Parse a list of networks in CIDR notation. Single addresses are treated as a network of their own.
+fn deserialize_networks<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<IpNet>, D::Error> {
+    let networks: Vec<String> = Vec::deserialize(deserializer)?;
+    networks
+        .iter()
+        .map(|network| {
+            network
+                .parse::<IpNet>()
+                .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| D::Error::custom(format!("Invalid network: {}", network)))
+        })
+        .collect()
 }
 
 fn webhook_mode_default() -> String {
@@ -32,11 +401,42 @@ pub struct Settings {
     pub port: i32,
     pub ssl_private_key: Option<PathBuf>,
     pub ssl_cert_chain: Option<PathBuf>,
+    pub ssl_client_ca: Option<PathBuf>,
+    pub ssl_client_cert_required: bool,
+    #[serde(default)]
+    pub client_certificates: Vec<ClientCertificate>,
     pub secret: Option<String>,
+    #[serde(default)]
+    pub secrets: Vec<Secret>,
     pub basic_auth_user: Option<String>,
     pub basic_auth_password: Option<String>,
     pub basic_auth_and_secret: bool,
+    #[serde(default)]
+    pub basic_auth_users: Vec<BasicAuthUser>,
+    pub signature_tolerance: i64,
+    pub replay_window: Option<i64>,
+    #[serde(default, deserialize_with = "deserialize_networks")]
+    pub allowed_ips: Vec<IpNet>,
+    #[serde(default, deserialize_with = "deserialize_networks")]
+    pub trusted_proxies: Vec<IpNet>,
+    pub lockout_threshold: u32,
+    pub lockout_seconds: i64,
+    pub lockout_max_seconds: i64,
     pub workers: usize,
+    pub max_queued: Option<usize>,
+    pub health_path: String,
+    pub ready_path: String,
+    pub ready_stall_timeout: i64,
+    pub log_level: String,
+    pub log_format: String,
+    pub log_dir: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub notification_log: Option<PathBuf>,
+    pub smtp: Option<Smtp>,
+    #[serde(default)]
+    pub api_tokens: Vec<ApiToken>,
+    #[serde(default)]
+    pub jwt_issuers: Vec<JwtIssuer>,
     pub webhooks: Vec<Webhook>,
 }
 
@@ -51,12 +451,36 @@ impl Clone for Settings {
             port: self.port,
             ssl_private_key: self.ssl_private_key.clone(),
             ssl_cert_chain: self.ssl_cert_chain.clone(),
+            ssl_client_ca: self.ssl_client_ca.clone(),
+            ssl_client_cert_required: self.ssl_client_cert_required,
+            client_certificates: self.client_certificates.clone(),
             secret: self.secret.clone(),
+            secrets: self.secrets.clone(),
             basic_auth_user: self.basic_auth_user.clone(),
             basic_auth_password: self.basic_auth_password.clone(),
             basic_auth_and_secret: self.basic_auth_and_secret,
+            basic_auth_users: self.basic_auth_users.clone(),
+            signature_tolerance: self.signature_tolerance,
+            replay_window: self.replay_window,
+            allowed_ips: self.allowed_ips.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+            lockout_threshold: self.lockout_threshold,
+            lockout_seconds: self.lockout_seconds,
+            lockout_max_seconds: self.lockout_max_seconds,
             workers: self.workers,
-            webhooks: webhooks,
+            max_queued: self.max_queued,
+            health_path: self.health_path.clone(),
+            ready_path: self.ready_path.clone(),
+            ready_stall_timeout: self.ready_stall_timeout,
+            log_level: self.log_level.clone(),
+            log_format: self.log_format.clone(),
+            log_dir: self.log_dir.clone(),
+            audit_log: self.audit_log.clone(),
+            notification_log: self.notification_log.clone(),
+            smtp: self.smtp.clone(),
+            api_tokens: self.api_tokens.clone(),
+            jwt_issuers: self.jwt_issuers.clone(),
+            webhooks,
         }
     }
 }
@@ -69,14 +493,32 @@ impl Settings {
         settings.set_default("port", "8000")?;
         settings.set_default("ssl_private_key", None::<String>)?;
         settings.set_default("ssl_cert_chain", None::<String>)?;
+        settings.set_default("ssl_client_ca", None::<String>)?;
+        settings.set_default("ssl_client_cert_required", false)?;
         settings.set_default("workers", 8)?;
+        settings.set_default("max_queued", None::<i64>)?;
+        settings.set_default("health_path", "/healthz")?;
+        settings.set_default("ready_path", "/readyz")?;
+        settings.set_default("ready_stall_timeout", 3600)?;
+        settings.set_default("log_level", "info")?;
+        settings.set_default("log_format", "human")?;
+        settings.set_default("log_dir", None::<String>)?;
+        settings.set_default("audit_log", None::<String>)?;
+        settings.set_default("notification_log", None::<String>)?;
+        settings.set_default("smtp", None::<String>)?;
         settings.set_default("secret", None::<String>)?;
         settings.set_default("basic_auth_user", None::<String>)?;
         settings.set_default("basic_auth_password", None::<String>)?;
         settings.set_default("basic_auth_and_secret", false)?;
+        settings.set_default("signature_tolerance", 300)?;
+        settings.set_default("replay_window", None::<i64>)?;
+        settings.set_default("lockout_threshold", 0)?;
+        settings.set_default("lockout_seconds", 60)?;
+        settings.set_default("lockout_max_seconds", 3600)?;
 
         settings = parse_config(settings)?;
-        let settings: Settings = settings.try_into()?;
+        settings.cache = resolve_secrets(settings.cache)?;
+        let mut settings: Settings = settings.try_into()?;
 
         if settings.basic_auth_password.is_some() || settings.basic_auth_user.is_some() {
             settings
@@ -91,18 +533,169 @@ impl Settings {
 
         // Verify that everything is in place, if `basic_auth_and_secret` is activated
         if settings.basic_auth_and_secret {
-            settings
-                .secret
-                .as_ref()
-                .ok_or(ConfigError::NotFound("secret".to_string()))?;
-            settings
-                .basic_auth_user
-                .as_ref()
-                .ok_or(ConfigError::NotFound("basic_auth_user".to_string()))?;
-            settings
-                .basic_auth_password
-                .as_ref()
-                .ok_or(ConfigError::NotFound("basic_auth_password".to_string()))?;
+            if settings.secrets.is_empty() {
+                settings
+                    .secret
+                    .as_ref()
+                    .ok_or(ConfigError::NotFound("secret".to_string()))?;
+            }
+            if settings.basic_auth_users.is_empty() {
+                settings
+                    .basic_auth_user
+                    .as_ref()
+                    .ok_or(ConfigError::NotFound("basic_auth_user".to_string()))?;
+                settings
+                    .basic_auth_password
+                    .as_ref()
+                    .ok_or(ConfigError::NotFound("basic_auth_password".to_string()))?;
+            }
+        }
+
+        if settings.signature_tolerance < 0
+            || settings.replay_window.is_some_and(|window| window <= 0)
+        {
+            return Err(anyhow!(
+                "signature_tolerance must not be negative and replay_window must be positive"
+            ));
+        }
+
+        if settings.lockout_seconds <= 0 || settings.lockout_max_seconds < settings.lockout_seconds
+        {
+            return Err(anyhow!(
+                "lockout_seconds must be positive and lockout_max_seconds must not be smaller"
+            ));
+        }
+
+        // Client certificates can only be verified with a CA
+        if (settings.ssl_client_cert_required || !settings.client_certificates.is_empty())
+            && settings.ssl_client_ca.is_none()
+        {
+            Err(ConfigError::NotFound("ssl_client_ca".to_string()))?;
+        }
+        if settings.ssl_client_ca.is_some() && settings.ssl_cert_chain.is_none() {
+            Err(ConfigError::NotFound("ssl_cert_chain".to_string()))?;
+        }
+        for (index, certificate) in settings.client_certificates.iter().enumerate() {
+            if certificate.name.is_empty()
+                || settings.client_certificates[..index]
+                    .iter()
+                    .any(|other| other.name == certificate.name)
+            {
+                return Err(anyhow!(
+                    "Client certificates need a unique name. Yours: {}",
+                    certificate.name
+                ));
+            }
+            if certificate.common_name.is_none() && certificate.san.is_none() {
+                return Err(anyhow!(
+                    "Client certificate {} needs a common_name or a san",
+                    certificate.name
+                ));
+            }
+            for webhook in &certificate.webhooks {
+                if !settings.webhooks.iter().any(|hook| &hook.name == webhook) {
+                    return Err(anyhow!(
+                        "Couldn't find webhook {} of client certificate {}",
+                        webhook,
+                        certificate.name
+                    ));
+                }
+            }
+        }
+
+        // Users are identified by their name and only store the hash of their password
+        for (index, user) in settings.basic_auth_users.iter().enumerate() {
+            if user.name.is_empty()
+                || user.name.contains(':')
+                || settings.basic_auth_users[..index]
+                    .iter()
+                    .any(|other| other.name == user.name)
+            {
+                return Err(anyhow!(
+                    "Basic auth users need a unique name without colons. Yours: {}",
+                    user.name
+                ));
+            }
+            if !is_supported_hash(&user.password_hash) {
+                return Err(anyhow!(
+                    "The password_hash of user {} must be an argon2 or bcrypt hash",
+                    user.name
+                ));
+            }
+            for webhook in &user.webhooks {
+                if !settings.webhooks.iter().any(|hook| &hook.name == webhook) {
+                    return Err(anyhow!(
+                        "Couldn't find webhook {} of user {}",
+                        webhook,
+                        user.name
+                    ));
+                }
+            }
+        }
+
+        // Rotated secrets are identified by their id in the logs
+        for (index, secret) in settings.secrets.iter().enumerate() {
+            if secret.id.is_empty()
+                || settings.secrets[..index]
+                    .iter()
+                    .any(|other| other.id == secret.id)
+            {
+                return Err(anyhow!("Secrets need a unique id. Yours: {}", secret.id));
+            }
+            if secret.secret.is_empty() {
+                return Err(anyhow!("Secret {} must not be empty", secret.id));
+            }
+        }
+
+        // API tokens are identified by their name and only store the hash of the token
+        for (index, token) in settings.api_tokens.iter().enumerate() {
+            if token.name.is_empty()
+                || settings.api_tokens[..index]
+                    .iter()
+                    .any(|other| other.name == token.name)
+            {
+                return Err(anyhow!(
+                    "API tokens need a unique name. Yours: {}",
+                    token.name
+                ));
+            }
+            if token.hash.len() != 64 || hex::decode(&token.hash).is_err() {
+                return Err(anyhow!(
+                    "The hash of API token {} must be a hex encoded SHA256 hash",
+                    token.name
+                ));
+            }
+            for webhook in &token.webhooks {
+                if !settings.webhooks.iter().any(|hook| &hook.name == webhook) {
+                    return Err(anyhow!(
+                        "Couldn't find webhook {} of API token {}",
+                        webhook,
+                        token.name
+                    ));
+                }
+            }
+        }
+
+        // JWT issuers are identified by their `iss` claim and need at least a single key
+        for index in 0..settings.jwt_issuers.len() {
+            let issuer = &settings.jwt_issuers[index];
+            if issuer.issuer.is_empty()
+                || settings.jwt_issuers[..index]
+                    .iter()
+                    .any(|other| other.issuer == issuer.issuer)
+            {
+                return Err(anyhow!(
+                    "JWT issuers need a unique issuer. Yours: {}",
+                    issuer.issuer
+                ));
+            }
+            if issuer.audience.is_empty() || issuer.scopes.is_empty() {
+                return Err(anyhow!(
+                    "JWT issuer {} needs an audience and at least a single scope",
+                    issuer.issuer
+                ));
+            }
+            settings.jwt_issuers[index].keys = load_keys(issuer)?;
         }
 
         // Webhook mode must be a valid
@@ -116,9 +709,157 @@ impl Settings {
             ));
         }
 
+        // Webhook names must not shadow the server's own routes
+        let health_name = settings.health_path.trim_start_matches('/');
+        let ready_name = settings.ready_path.trim_start_matches('/');
+        for webhook in &settings.webhooks {
+            if RESERVED_NAMES.contains(&webhook.name.as_str())
+                || webhook.name == health_name
+                || webhook.name == ready_name
+            {
+                return Err(anyhow!(
+                    "Webhook name is reserved for an internal route: {}",
+                    webhook.name
+                ));
+            }
+        }
+
+        // A webhook either has a single command or a list of steps
+        for webhook in &settings.webhooks {
+            if webhook.command.is_empty() == webhook.steps.is_empty() {
+                return Err(anyhow!(
+                    "Webhook needs either a command or steps, but not both. Yours: {}",
+                    webhook.name
+                ));
+            }
+        }
+
+        // Follow-ups must point to existing webhooks
+        for webhook in &settings.webhooks {
+            for follow_up in webhook.on_success.iter().chain(webhook.on_failure.iter()) {
+                if !settings
+                    .webhooks
+                    .iter()
+                    .any(|hook| hook.name == follow_up.webhook)
+                {
+                    return Err(anyhow!(
+                        "Couldn't find follow-up webhook {} of webhook {}",
+                        follow_up.webhook,
+                        webhook.name
+                    ));
+                }
+            }
+        }
+
+        // Follow-ups must not trigger each other endlessly
+        if let Some(cycle) = find_follow_up_cycle(&settings.webhooks) {
+            return Err(anyhow!(
+                "Follow-ups of webhooks must not form a cycle: {}",
+                cycle.join(" -> ")
+            ));
+        }
+
+        // Webhooks, that require certificates and signatures, need a secret
+        for webhook in &settings.webhooks {
+            if webhook.client_certificate_and_secret
+                && settings.secret.is_none()
+                && settings.secrets.is_empty()
+            {
+                return Err(anyhow!(
+                    "client_certificate_and_secret needs a secret. Yours: {}",
+                    webhook.name
+                ));
+            }
+        }
+
+        // Retries need at least a single attempt and a positive backoff
+        for webhook in &settings.webhooks {
+            if let Some(retry) = &webhook.retry {
+                if retry.max_attempts == 0 || retry.backoff_seconds < 0 {
+                    return Err(anyhow!(
+                        "Retry max_attempts must be at least 1 and backoff_seconds must not be negative. Yours: {}",
+                        webhook.name
+                    ));
+                }
+            }
+        }
+
+        // Rate limits need to allow at least a single request
+        for webhook in &settings.webhooks {
+            if let Some(rate_limit) = &webhook.rate_limit {
+                if rate_limit.requests == 0 || rate_limit.seconds <= 0 {
+                    return Err(anyhow!(
+                        "Rate limit requests and seconds must be at least 1. Yours: {}",
+                        webhook.name
+                    ));
+                }
+            }
+        }
+
+        // Notifications need a target and at least a single delivery attempt
+        for webhook in &settings.webhooks {
+            for notify in &webhook.notify {
+                if notify.url.is_empty() || notify.max_attempts == 0 {
+                    return Err(anyhow!(
+                        "Notify targets need a url and max_attempts must be at least 1. Yours: {}",
+                        webhook.name
+                    ));
+                }
+                if notify.kind == NotifyKind::Matrix
+                    && (notify.room_id.is_none() || notify.access_token.is_none())
+                {
+                    return Err(anyhow!(
+                        "Matrix notify targets need a room_id and an access_token. Yours: {}",
+                        webhook.name
+                    ));
+                }
+            }
+        }
+
+        // Gitea doesn't have a default instance
+        for webhook in &settings.webhooks {
+            if let Some(commit_status) = &webhook.commit_status {
+                if commit_status.provider == GitProvider::Gitea && commit_status.base_url.is_none()
+                {
+                    return Err(anyhow!(
+                        "Gitea commit statuses need a base_url. Yours: {}",
+                        webhook.name
+                    ));
+                }
+            }
+        }
+
+        // Email notifications need a mail server and recipients
+        for webhook in &settings.webhooks {
+            if let Some(email) = &webhook.email {
+                if settings.smtp.is_none() || email.to.is_empty() {
+                    return Err(anyhow!(
+                        "Email notifications need the smtp settings and at least one recipient. Yours: {}",
+                        webhook.name
+                    ));
+                }
+            }
+        }
+
         Ok(settings)
     }
 
+    /// All secrets, that are currently accepted for signatures, with their id.
+    /// The plain `secret` has the id `secret`.
+    pub fn get_active_secrets(&self, now: DateTime<Local>) -> Vec<(&str, &str)> {
+        let mut secrets: Vec<(&str, &str)> = self
+            .secrets
+            .iter()
+            .filter(|secret| secret.expires.is_none_or(|expires| now < expires))
+            .map(|secret| (secret.id.as_str(), secret.secret.as_str()))
+            .collect();
+        if let Some(secret) = self.secret.as_deref().filter(|secret| !secret.is_empty()) {
+            secrets.push(("secret", secret));
+        }
+
+        secrets
+    }
+
     /// Get settings for a specific webhook
     pub fn get_webhook_by_name(&self, name: &String) -> Result<Webhook, HttpResponse> {
         for webhook in self.webhooks.iter() {
@@ -149,8 +890,50 @@ fn parse_config(mut settings: Config) -> Result<Config> {
     Ok(settings)
 }
 
+/// Find a chain of follow-ups, that leads back to one of its webhooks.
+/// The returned chain starts and ends with the same webhook.
+fn find_follow_up_cycle(webhooks: &[Webhook]) -> Option<Vec<String>> {
+    fn visit<'a>(
+        webhooks: &'a [Webhook],
+        name: &'a str,
+        path: &mut Vec<&'a str>,
+        finished: &mut Vec<&'a str>,
+    ) -> Option<Vec<String>> {
+        if let Some(start) = path.iter().position(|visited| *visited == name) {
+            let mut cycle: Vec<String> =
+                path[start..].iter().map(|name| name.to_string()).collect();
+            cycle.push(name.to_string());
+            return Some(cycle);
+        }
+        if finished.contains(&name) {
+            return None;
+        }
+
+        path.push(name);
+        let webhook = webhooks.iter().find(|webhook| webhook.name == name)?;
+        for follow_up in webhook.on_success.iter().chain(webhook.on_failure.iter()) {
+            if let Some(cycle) = visit(webhooks, &follow_up.webhook, path, finished) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        finished.push(name);
+
+        None
+    }
+
+    let mut finished = Vec::new();
+    for webhook in webhooks {
+        if let Some(cycle) = visit(webhooks, &webhook.name, &mut Vec::new(), &mut finished) {
+            return Some(cycle);
+        }
+    }
+
+    None
+}
+
 #[cfg(target_os = "linux")]
-fn get_config_paths() -> Result<Vec<PathBuf>> {
+pub fn get_config_paths() -> Result<Vec<PathBuf>> {
     let mut paths = Vec::new();
     let home_dir = dirs::home_dir().ok_or(anyhow!("Couldn't resolve home dir"))?;
     paths.push(Path::new("/etc/webhook_server.yml").to_path_buf());
@@ -161,7 +944,7 @@ fn get_config_paths() -> Result<Vec<PathBuf>> {
 }
 
 #[cfg(target_os = "windows")]
-fn get_config_paths() -> Result<Vec<PathBuf>> {
+pub fn get_config_paths() -> Result<Vec<PathBuf>> {
     let mut paths = Vec::new();
 
     let home_dir = dirs::home_dir().ok_or(anyhow!("Couldn't resolve home dir"))?;
@@ -172,7 +955,7 @@ fn get_config_paths() -> Result<Vec<PathBuf>> {
 }
 
 #[cfg(target_os = "macos")]
-fn get_config_paths() -> Result<Vec<PathBuf>> {
+pub fn get_config_paths() -> Result<Vec<PathBuf>> {
     let mut paths = Vec::new();
 
     let home_dir = dirs::home_dir().ok_or(anyhow!("Couldn't resolve home dir"))?;
@@ -182,3 +965,42 @@ fn get_config_paths() -> Result<Vec<PathBuf>> {
 
     Ok(paths)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webhooks(yaml: &str) -> Vec<Webhook> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_follow_up_cycles() {
+        let chain = webhooks(
+            "
+- {name: build, cwd: /tmp, command: make, on_success: [{webhook: deploy}]}
+- {name: deploy, cwd: /tmp, command: deploy, on_failure: [{webhook: rollback}]}
+- {name: rollback, cwd: /tmp, command: rollback}
+",
+        );
+        assert_eq!(find_follow_up_cycle(&chain), None);
+
+        let cycle = webhooks(
+            "
+- {name: build, cwd: /tmp, command: make, on_success: [{webhook: deploy}]}
+- {name: deploy, cwd: /tmp, command: deploy, on_failure: [{webhook: build}]}
+",
+        );
+        assert_eq!(
+            find_follow_up_cycle(&cycle),
+            Some(vec!["build".into(), "deploy".into(), "build".into()])
+        );
+
+        let own =
+            webhooks("- {name: poll, cwd: /tmp, command: poll, on_failure: [{webhook: poll}]}");
+        assert_eq!(
+            find_follow_up_cycle(&own),
+            Some(vec!["poll".into(), "poll".into()])
+        );
+    }
+}
@@ -1,14 +1,23 @@
 use ::actix::prelude::*;
 use ::log::{info, warn};
-use ::subprocess::{CaptureData, Exec, ExitStatus, Redirection};
+use ::std::collections::HashMap;
+use ::std::fs::{create_dir_all, File};
+use ::std::io::ErrorKind;
+use ::std::io::Write;
+use ::std::path::Path;
+use ::std::time::{Duration, Instant};
+use ::subprocess::{Exec, ExitStatus, Redirection};
 
 use crate::messages::*;
-use crate::scheduler::Scheduler;
+use crate::metrics::TASK_DURATION;
+use crate::task::step::{StepResult, TaskStep};
+
+/// The exit code of processes that have been killed due to a timeout.
+/// This is the same code coreutils' `timeout` uses.
+const TIMEOUT_EXIT_CODE: u32 = 124;
 
 /// The Actor that's actually responsible for executing tasks
-pub struct TaskExecutor {
-    pub scheduler: Option<Addr<Scheduler>>,
-}
+pub struct TaskExecutor;
 
 impl Actor for TaskExecutor {
     type Context = SyncContext<Self>;
@@ -20,38 +29,33 @@ impl Handler<StartTask> for TaskExecutor {
     type Result = ();
 
     fn handle(&mut self, task: StartTask, _context: &mut Self::Context) {
-        info!("Starting Task: {}", task.command);
-
-        let result = Exec::shell(task.command)
-            .cwd(task.cwd)
-            .stdout(Redirection::Pipe)
-            .stderr(Redirection::Pipe)
-            .capture();
-
-        let captured_data: CaptureData;
-
-        match result {
-            Ok(data) => {
-                captured_data = data;
-            }
-            Err(error) => {
-                warn!("Error during task execution: {}", error);
-                return;
-            }
-        }
+        let start = Instant::now();
+        let (exit_code, stdout, stderr, step_results) = if task.steps.is_empty() {
+            info!(
+                webhook = task.webhook_name.as_str(), task_id = task.task_id;
+                "Starting Task: {}", task.command
+            );
+            let output = run_command(&task.command, &task.cwd, &HashMap::new(), None);
+            (output.exit_code, output.stdout, output.stderr, Vec::new())
+        } else {
+            info!(
+                webhook = task.webhook_name.as_str(), task_id = task.task_id;
+                "Starting Task: {} with {} steps", task.webhook_name, task.steps.len()
+            );
+            run_steps(task.steps)
+        };
 
-        let mut exit_code = 1;
-        match captured_data.exit_status {
-            ExitStatus::Exited(_exit_code) => {
-                exit_code = _exit_code;
+        if let Some(path) = &task.log_file {
+            if let Err(error) = write_log_file(path, &stdout, &stderr, &step_results) {
+                warn!(
+                    webhook = task.webhook_name.as_str(), task_id = task.task_id;
+                    "Couldn't write log file {:?}: {}", path, error
+                );
             }
-            ExitStatus::Signaled(_signal) => {}
-            ExitStatus::Other(_other) => {}
-            ExitStatus::Undetermined => {}
         }
-
-        let stdout = captured_data.stdout_str();
-        let stderr = captured_data.stderr_str();
+        TASK_DURATION
+            .with_label_values(&[&task.webhook_name])
+            .observe(start.elapsed().as_secs_f64());
 
         let message = TaskCompleted {
             webhook_name: task.webhook_name,
@@ -59,8 +63,201 @@ impl Handler<StartTask> for TaskExecutor {
             exit_code,
             stdout,
             stderr,
+            step_results,
         };
 
         task.scheduler.do_send(message);
     }
 }
+
+/// The captured output of a single process
+struct CommandOutput {
+    exit_code: u32,
+    stdout: String,
+    stderr: String,
+}
+
+/// Write the output of a task to its log file.
+/// For pipelines, the output of each step is written in a section of its own.
+fn write_log_file(
+    path: &Path,
+    stdout: &str,
+    stderr: &str,
+    step_results: &[StepResult],
+) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut file = File::create(path)?;
+
+    if step_results.is_empty() {
+        file.write_all(stdout.as_bytes())?;
+        file.write_all(stderr.as_bytes())?;
+        return Ok(());
+    }
+
+    for step in step_results {
+        writeln!(file, "=== {} (exit code {}) ===", step.name, step.exit_code)?;
+        file.write_all(step.stdout.as_bytes())?;
+        file.write_all(step.stderr.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+/// Execute all steps one after another.
+/// Execution stops at the first failing step, unless it's allowed to fail.
+/// The output of all executed steps is combined for the task's output.
+fn run_steps(steps: Vec<TaskStep>) -> (u32, String, String, Vec<StepResult>) {
+    let mut exit_code = 0;
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    let mut step_results = Vec::new();
+
+    for step in steps {
+        info!("Starting step {}: {}", step.name, step.command);
+        let start = Instant::now();
+        let output = run_command(&step.command, &step.cwd, &step.env, step.timeout);
+        let duration = start.elapsed().as_secs_f64();
+
+        stdout.push_str(&output.stdout);
+        stderr.push_str(&output.stderr);
+        step_results.push(StepResult {
+            name: step.name.clone(),
+            exit_code: output.exit_code,
+            stdout: output.stdout,
+            stderr: output.stderr,
+            duration,
+        });
+
+        if output.exit_code != 0 && !step.continue_on_error {
+            info!("Step {} failed, skipping remaining steps", step.name);
+            exit_code = output.exit_code;
+            break;
+        }
+    }
+
+    (exit_code, stdout, stderr, step_results)
+}
+
+/// Run a command in the system shell and capture its output.
+/// The process is killed, if it doesn't finish before the timeout (in seconds).
+fn run_command(
+    command: &str,
+    cwd: &str,
+    env: &HashMap<String, String>,
+    timeout: Option<u64>,
+) -> CommandOutput {
+    let mut exec = Exec::shell(command)
+        .cwd(cwd)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe);
+    for (key, value) in env.iter() {
+        exec = exec.env(key, value);
+    }
+
+    let mut process = match exec.popen() {
+        Ok(process) => process,
+        Err(error) => {
+            warn!("Error during task execution: {}", error);
+            return CommandOutput {
+                exit_code: 1,
+                stdout: String::new(),
+                stderr: format!("Error during task execution: {}", error),
+            };
+        }
+    };
+
+    let mut communicator = process.communicate_start(None);
+    if let Some(timeout) = timeout {
+        communicator = communicator.limit_time(Duration::from_secs(timeout));
+    }
+
+    let mut timed_out = false;
+    let (stdout, mut stderr) = match communicator.read() {
+        Ok(capture) => capture,
+        Err(error) => {
+            timed_out = error.kind() == ErrorKind::TimedOut;
+            if !timed_out {
+                warn!("Error while reading task output: {}", error.error);
+            }
+            error.capture
+        }
+    };
+
+    if timed_out {
+        warn!("Killing process after timeout: {}", command);
+        let _ = process.kill();
+        let message = format!("\nProcess has been killed after {}s\n", timeout.unwrap());
+        stderr
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(message.as_bytes());
+    }
+
+    let mut exit_code = 1;
+    match process.wait() {
+        Ok(ExitStatus::Exited(_exit_code)) => exit_code = _exit_code,
+        Ok(_) => {}
+        Err(error) => warn!("Error while waiting for process: {}", error),
+    }
+    if timed_out {
+        exit_code = TIMEOUT_EXIT_CODE;
+    }
+
+    CommandOutput {
+        exit_code,
+        stdout: String::from_utf8_lossy(&stdout.unwrap_or_default()).to_string(),
+        stderr: String::from_utf8_lossy(&stderr.unwrap_or_default()).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(command: &str, continue_on_error: bool, timeout: Option<u64>) -> TaskStep {
+        TaskStep {
+            name: command.to_string(),
+            command: command.to_string(),
+            cwd: "/tmp".to_string(),
+            env: HashMap::new(),
+            timeout,
+            continue_on_error,
+        }
+    }
+
+    #[test]
+    /// Execution stops at the first failing step
+    fn test_failing_step_aborts() {
+        let steps = vec![
+            step("echo one", false, None),
+            step("exit 3", false, None),
+            step("echo two", false, None),
+        ];
+        let (exit_code, stdout, _, results) = run_steps(steps);
+
+        assert_eq!(exit_code, 3);
+        assert_eq!(stdout, "one\n");
+        assert_eq!(results.len(), 2);
+    }
+
+    #[test]
+    /// Steps that may fail don't stop the execution
+    fn test_continue_on_error() {
+        let steps = vec![step("exit 3", true, None), step("echo two", false, None)];
+        let (exit_code, stdout, _, results) = run_steps(steps);
+
+        assert_eq!(exit_code, 0);
+        assert_eq!(stdout, "two\n");
+        assert_eq!(results[0].exit_code, 3);
+    }
+
+    #[test]
+    /// Steps are killed after their timeout
+    fn test_step_timeout() {
+        let (exit_code, _, _, results) = run_steps(vec![step("sleep 10", false, Some(1))]);
+
+        assert_eq!(exit_code, TIMEOUT_EXIT_CODE);
+        assert!(results[0].duration < 10.0);
+    }
+}
@@ -1,3 +1,7 @@
 pub mod executor;
 pub mod queue;
+pub mod statistics;
+pub mod step;
+#[allow(clippy::module_inception)]
 pub mod task;
+pub mod template;
@@ -1,10 +1,14 @@
 use std::collections::{BTreeMap, HashMap};
 
+use chrono::prelude::*;
+use log::info;
 use serde::Serialize;
 
-use crate::messages::NewTask;
 use crate::messages::TaskCompleted;
+use crate::messages::{NewTask, NewTaskResult, TaskOverview};
+use crate::metrics::*;
 use crate::settings::Settings;
+use crate::task::statistics::{get_statistics, WebhookStatistics};
 use crate::task::task::Task;
 
 /// The TaskQueue represents the current state of all tasks and is also
@@ -20,6 +24,7 @@ pub struct TaskQueue {
     running_count: HashMap<String, i32>,
     queued_count: HashMap<String, i32>,
 
+    scheduled: BTreeMap<i32, Task>,
     queued: BTreeMap<i32, Task>,
     running: BTreeMap<i32, Task>,
     finished: BTreeMap<i32, Task>,
@@ -41,42 +46,200 @@ impl TaskQueue {
             settings: settings.clone(),
             queued_count,
             running_count,
+            scheduled: BTreeMap::new(),
             queued: BTreeMap::new(),
             running: BTreeMap::new(),
             finished: BTreeMap::new(),
         }
     }
 
-    /// Decide whether a new task should be added to the queue
-    pub fn add_task(&mut self, incoming: NewTask) {
+    /// Decide whether a new task should be added to the queue.
+    /// Tasks with a `run_at` in the future are put aside until they're due.
+    pub fn add_task(&mut self, incoming: NewTask) -> NewTaskResult {
         let name = incoming.webhook_name.clone();
-        let settings = self.settings.get_webhook_by_name(&name).unwrap();
 
-        // Check whether the task should be added
-        // single only allows a single task either running or queued
-        // deploy allows a single running AND a single queued
-        // parallel always adds the task
-        match settings.mode.as_str() {
-            "single" => {
-                if *self.queued_count.get(&name).unwrap() > 0 {
-                    return;
-                } else if *self.running_count.get(&name).unwrap() > 0 {
-                    return;
-                }
-            }
-            "deploy" => {
-                if *self.queued_count.get(&name).unwrap() > 0 {
-                    return;
-                }
+        let is_scheduled = incoming.run_at.is_some_and(|run_at| run_at > Local::now());
+        if !is_scheduled {
+            if let Some(task_id) = self.get_covering_task(&name) {
+                TASKS_DROPPED.with_label_values(&[&name, "coalesced"]).inc();
+                return NewTaskResult::Coalesced(task_id);
             }
-            "parallel" => {}
-            _ => return,
+        }
+
+        if self.queue_is_full(&name) {
+            info!("Queue is full, rejecting task for: {}", name);
+            TASKS_DROPPED.with_label_values(&[&name, "rejected"]).inc();
+            return NewTaskResult::Rejected;
         }
 
         self.max_id += 1;
         let task = Task::new(incoming, self.max_id);
-        self.queued.insert(self.max_id, task);
-        self.increment_queued_count(&name);
+        if is_scheduled {
+            self.scheduled.insert(self.max_id, task);
+        } else {
+            self.queued.insert(self.max_id, task);
+            self.increment_queued_count(&name);
+        }
+        TASKS_ENQUEUED.with_label_values(&[&name]).inc();
+
+        NewTaskResult::Added(self.max_id)
+    }
+
+    /// Move all scheduled tasks, whose time has come, into the queue.
+    /// The usual mode restrictions apply at this point.
+    pub fn queue_due_tasks(&mut self) {
+        let now = Local::now();
+        let due_ids: Vec<i32> = self
+            .scheduled
+            .iter()
+            .filter(|(_, task)| task.run_at.is_none_or(|run_at| run_at <= now))
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in due_ids {
+            let task = self.scheduled.remove(&id).unwrap();
+            if self.get_covering_task(&task.webhook_name).is_some() {
+                info!(
+                    "Dropping scheduled task: {} - {}",
+                    task.webhook_name, task.task_id
+                );
+                TASKS_DROPPED
+                    .with_label_values(&[&task.webhook_name, "coalesced"])
+                    .inc();
+                continue;
+            }
+
+            self.increment_queued_count(&task.webhook_name);
+            self.queued.insert(id, task);
+        }
+    }
+
+    /// Remove a task, that hasn't been started yet.
+    /// Returns the task, if a scheduled or queued task with this id existed.
+    pub fn cancel_task(&mut self, task_id: i32) -> Option<Task> {
+        if let Some(task) = self.scheduled.remove(&task_id) {
+            return Some(task);
+        }
+
+        if let Some(task) = self.queued.remove(&task_id) {
+            self.decrement_queued_count(&task.webhook_name);
+            return Some(task);
+        }
+
+        None
+    }
+
+    /// Get a copy of all unfinished and the most recently finished tasks
+    /// Only the tasks of the given webhooks are included, if there are any.
+    pub fn get_overview(&self, finished_limit: usize, webhooks: Option<&[String]>) -> TaskOverview {
+        let visible = |task: &&Task| is_visible(task, webhooks);
+        TaskOverview {
+            scheduled: self.scheduled.values().filter(visible).cloned().collect(),
+            queued: self.queued.values().filter(visible).cloned().collect(),
+            running: self.running.values().filter(visible).cloned().collect(),
+            finished: self
+                .finished
+                .values()
+                .rev()
+                .filter(visible)
+                .take(finished_limit)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Get a copy, that only contains the tasks and counters of the given webhooks
+    pub fn filter_webhooks(&self, webhooks: &[String]) -> TaskQueue {
+        let filter_tasks = |tasks: &BTreeMap<i32, Task>| {
+            tasks
+                .iter()
+                .filter(|(_, task)| webhooks.contains(&task.webhook_name))
+                .map(|(id, task)| (*id, task.clone()))
+                .collect()
+        };
+        let filter_counts = |counts: &HashMap<String, i32>| {
+            counts
+                .iter()
+                .filter(|(name, _)| webhooks.contains(name))
+                .map(|(name, count)| (name.clone(), *count))
+                .collect()
+        };
+
+        TaskQueue {
+            max_id: self.max_id,
+            settings: self.settings.clone(),
+            running_count: filter_counts(&self.running_count),
+            queued_count: filter_counts(&self.queued_count),
+            scheduled: filter_tasks(&self.scheduled),
+            queued: filter_tasks(&self.queued),
+            running: filter_tasks(&self.running),
+            finished: filter_tasks(&self.finished),
+        }
+    }
+
+    /// Get a copy of any task by its id
+    pub fn get_task(&self, task_id: i32) -> Option<Task> {
+        self.get_pending_task(task_id)
+            .or_else(|| self.finished.get(&task_id))
+            .cloned()
+    }
+
+    /// Get a task, that hasn't been finished yet
+    pub fn get_pending_task(&self, task_id: i32) -> Option<&Task> {
+        self.scheduled
+            .get(&task_id)
+            .or_else(|| self.queued.get(&task_id))
+            .or_else(|| self.running.get(&task_id))
+    }
+
+    /// Get the id of an existing task, that makes a new task of this webhook redundant.
+    /// single only allows a single task either running or queued
+    /// deploy allows a single running AND a single queued
+    /// parallel always adds the task
+    fn get_covering_task(&self, name: &String) -> Option<i32> {
+        let settings = self.settings.get_webhook_by_name(name).unwrap();
+        let queued = self
+            .queued
+            .values()
+            .find(|task| &task.webhook_name == name)
+            .map(|task| task.task_id);
+
+        match settings.mode.as_str() {
+            "single" => queued.or_else(|| {
+                self.running
+                    .values()
+                    .find(|task| &task.webhook_name == name)
+                    .map(|task| task.task_id)
+            }),
+            "deploy" => queued,
+            _ => None,
+        }
+    }
+
+    /// Check whether the per-webhook or global `max_queued` limit has been reached.
+    /// Scheduled tasks count towards these limits as well.
+    fn queue_is_full(&self, name: &String) -> bool {
+        let settings = self.settings.get_webhook_by_name(name).unwrap();
+
+        if let Some(max_queued) = settings.max_queued {
+            let scheduled = self
+                .scheduled
+                .values()
+                .filter(|task| &task.webhook_name == name)
+                .count();
+            let queued = *self.queued_count.get(name).unwrap() as usize;
+            if scheduled + queued >= max_queued {
+                return true;
+            }
+        }
+
+        if let Some(max_queued) = self.settings.max_queued {
+            if self.scheduled.len() + self.queued.len() >= max_queued {
+                return true;
+            }
+        }
+
+        false
     }
 
     /// Get all tasks that can be dispatched right now.
@@ -110,19 +273,17 @@ impl TaskQueue {
                     continue;
                 }
                 // Deploy can always be scheduled, if there isn't already one running
-                "deploy" => {
-                    if *self.running_count.get(&name).unwrap() == 0 {
-                        self.schedule_task(task, &mut tasks);
-                        continue;
-                    }
+                "deploy" if *self.running_count.get(&name).unwrap() == 0 => {
+                    self.schedule_task(task, &mut tasks);
+                    continue;
                 }
                 // Parallel can be scheduled, if there are less than the max
                 // specified parallel parallel_processes running
-                "parallel" => {
-                    if *self.running_count.get(&name).unwrap() < settings.parallel_processes {
-                        self.schedule_task(task, &mut tasks);
-                        continue;
-                    }
+                "parallel"
+                    if *self.running_count.get(&name).unwrap() < settings.parallel_processes =>
+                {
+                    self.schedule_task(task, &mut tasks);
+                    continue;
                 }
                 _ => {}
             }
@@ -135,39 +296,394 @@ impl TaskQueue {
     }
 
     /// A task has finished. Remove it from running and insert new data from the finished process
-    pub fn finish_task(&mut self, completed: TaskCompleted) {
+    pub fn finish_task(&mut self, completed: TaskCompleted) -> Task {
         let mut task = self.running.remove(&completed.task_id).unwrap();
         task.exit_code = Some(completed.exit_code);
         task.stdout = Some(completed.stdout);
         task.stderr = Some(completed.stderr);
+        task.step_results = completed.step_results;
+
+        let finished_at = Local::now();
+        if let Some(started_at) = task.started_at {
+            task.duration = Some(seconds_between(started_at, finished_at));
+        }
+        task.finished_at = Some(finished_at);
 
         self.decrement_running_count(&task.webhook_name);
-        self.finished.insert(task.task_id, task);
+
+        // The output already lives in the log file, don't keep it in memory
+        if task.log_file.is_some() {
+            let mut stored = task.clone();
+            stored.stdout = None;
+            stored.stderr = None;
+            for step in stored.step_results.iter_mut() {
+                step.stdout.clear();
+                step.stderr.clear();
+            }
+            self.finished.insert(task.task_id, stored);
+        } else {
+            self.finished.insert(task.task_id, task.clone());
+        }
+        TASKS_FINISHED
+            .with_label_values(&[&task.webhook_name, &completed.exit_code.to_string()])
+            .inc();
+
+        task
+    }
+
+    /// Schedule the next attempt of a failed task.
+    /// Retries aren't subject to the `max_queued` limits.
+    pub fn retry_task(&mut self, task: &Task, run_at: DateTime<Local>) -> i32 {
+        self.max_id += 1;
+        let retry = task.retry(self.max_id, run_at);
+        self.scheduled.insert(self.max_id, retry);
+        TASKS_ENQUEUED
+            .with_label_values(&[&task.webhook_name])
+            .inc();
+
+        self.max_id
     }
 
     /// Helper to easily change the state of a task to running
-    fn schedule_task(&mut self, task: Task, tasks: &mut Vec<Task>) {
+    fn schedule_task(&mut self, mut task: Task, tasks: &mut Vec<Task>) {
         let name = task.webhook_name.clone();
         // Decrement queued count
-        let queued = self.queued_count.get(&name).unwrap().clone();
+        let queued = self.queued_count.get(&name).unwrap();
         self.queued_count.insert(name.clone(), queued - 1);
 
         // Increment running count
-        let running = self.running_count.get(&name).unwrap().clone();
+        let running = self.running_count.get(&name).unwrap();
         self.running_count.insert(name, running + 1);
 
+        // Remember when the task has been started and how long it waited since it has been due
+        let started_at = Local::now();
+        let queue_wait = seconds_between(task.due_at(), started_at);
+        task.started_at = Some(started_at);
+        task.queue_wait = Some(queue_wait);
+        if let Some(log_dir) = &self.settings.log_dir {
+            let path = log_dir
+                .join(&task.webhook_name)
+                .join(format!("{}.log", task.task_id));
+            task.log_file = Some(path);
+        }
+        TASK_WAIT
+            .with_label_values(&[&task.webhook_name])
+            .observe(queue_wait);
+        TASKS_STARTED.with_label_values(&[&task.webhook_name]).inc();
+
         // Push task into running
         self.running.insert(task.task_id, task.clone());
         tasks.push(task)
     }
 
+    /// Get aggregated statistics of the finished tasks for each webhook
+    pub fn get_statistics(
+        &self,
+        webhooks: Option<&[String]>,
+    ) -> HashMap<String, WebhookStatistics> {
+        let names = self
+            .settings
+            .webhooks
+            .iter()
+            .map(|webhook| &webhook.name)
+            .filter(|name| webhooks.is_none_or(|webhooks| webhooks.contains(name)));
+        get_statistics(names, self.finished.values())
+    }
+
+    /// All workers are busy, while there are still tasks waiting in the queue
+    pub fn is_saturated(&self) -> bool {
+        self.running.len() >= self.settings.workers && !self.queued.is_empty()
+    }
+
+    /// Update the gauges of queued, scheduled and running tasks
+    pub fn update_metrics(&self) {
+        for webhook in self.settings.webhooks.iter() {
+            let name = &webhook.name;
+            let scheduled = self
+                .scheduled
+                .values()
+                .filter(|task| &task.webhook_name == name)
+                .count();
+            TASKS_SCHEDULED
+                .with_label_values(&[name])
+                .set(scheduled as i64);
+            TASKS_QUEUED
+                .with_label_values(&[name])
+                .set(*self.queued_count.get(name).unwrap() as i64);
+            TASKS_RUNNING
+                .with_label_values(&[name])
+                .set(*self.running_count.get(name).unwrap() as i64);
+        }
+        WORKERS_BUSY.set(self.running.len() as i64);
+    }
+
     fn increment_queued_count(&mut self, name: &String) {
-        let value = self.queued_count.get(name).unwrap().clone();
+        let value = self.queued_count.get(name).unwrap();
         self.queued_count.insert(name.clone(), value + 1);
     }
 
+    fn decrement_queued_count(&mut self, name: &String) {
+        let value = self.queued_count.get(name).unwrap();
+        self.queued_count.insert(name.clone(), value - 1);
+    }
+
     fn decrement_running_count(&mut self, name: &String) {
-        let value = self.running_count.get(name).unwrap().clone();
+        let value = self.running_count.get(name).unwrap();
         self.running_count.insert(name.clone(), value - 1);
     }
 }
+
+/// The time between two points in time in seconds. Negative durations are treated as zero.
+fn seconds_between(start: DateTime<Local>, end: DateTime<Local>) -> f64 {
+    (end - start).to_std().unwrap_or_default().as_secs_f64()
+}
+
+/// Check whether the task belongs to one of the webhooks. All tasks are visible without a filter.
+fn is_visible(task: &Task, webhooks: Option<&[String]>) -> bool {
+    webhooks.is_none_or(|webhooks| webhooks.contains(&task.webhook_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+    use crate::settings::{Provider, Webhook};
+
+    fn setup_queue(mode: &str) -> TaskQueue {
+        let webhook = Webhook {
+            name: "test".to_string(),
+            command: "/bin/true".to_string(),
+            cwd: "/tmp".to_string(),
+            mode: mode.to_string(),
+            provider: Provider::Generic,
+            allowed_ips: Vec::new(),
+            jwt_claims: HashMap::new(),
+            client_certificate_and_secret: false,
+            rate_limit: None,
+            parallel_processes: 2,
+            max_queued: None,
+            retry: None,
+            on_success: Vec::new(),
+            on_failure: Vec::new(),
+            steps: Vec::new(),
+            notify: Vec::new(),
+            email: None,
+            commit_status: None,
+        };
+        let settings = Settings {
+            domain: String::new(),
+            port: 8000,
+            ssl_private_key: None,
+            ssl_cert_chain: None,
+            ssl_client_ca: None,
+            ssl_client_cert_required: false,
+            client_certificates: Vec::new(),
+            secret: None,
+            secrets: Vec::new(),
+            basic_auth_user: None,
+            basic_auth_password: None,
+            basic_auth_and_secret: false,
+            basic_auth_users: Vec::new(),
+            signature_tolerance: 300,
+            replay_window: None,
+            allowed_ips: Vec::new(),
+            trusted_proxies: Vec::new(),
+            lockout_threshold: 0,
+            lockout_seconds: 60,
+            lockout_max_seconds: 3600,
+            workers: 8,
+            max_queued: None,
+            health_path: "/healthz".to_string(),
+            ready_path: "/readyz".to_string(),
+            ready_stall_timeout: 3600,
+            log_level: "info".to_string(),
+            log_format: "human".to_string(),
+            log_dir: None,
+            audit_log: None,
+            notification_log: None,
+            smtp: None,
+            api_tokens: Vec::new(),
+            jwt_issuers: Vec::new(),
+            webhooks: vec![webhook],
+        };
+
+        TaskQueue::new(settings)
+    }
+
+    fn new_task(run_at: Option<DateTime<Local>>) -> NewTask {
+        NewTask {
+            webhook_name: "test".to_string(),
+            parameters: HashMap::new(),
+            cwd: "/tmp".to_string(),
+            command: "/bin/true".to_string(),
+            steps: Vec::new(),
+            added_at: Local::now(),
+            run_at,
+            parent_id: None,
+            commit: None,
+        }
+    }
+
+    #[test]
+    /// Tasks with a run_at in the future aren't dispatched until they're due
+    fn test_scheduled_task_waits() {
+        let mut queue = setup_queue("parallel");
+        queue.add_task(new_task(Some(Local::now() + Duration::hours(1))));
+
+        queue.queue_due_tasks();
+        assert_eq!(queue.scheduled.len(), 1);
+        assert!(queue.get_tasks_for_dispatch().is_empty());
+    }
+
+    #[test]
+    /// Due tasks are moved into the queue and can be dispatched
+    fn test_due_task_is_queued() {
+        let mut queue = setup_queue("parallel");
+        queue.add_task(new_task(Some(Local::now() + Duration::hours(1))));
+        queue.scheduled.get_mut(&1).unwrap().run_at = Some(Local::now());
+
+        queue.queue_due_tasks();
+        assert!(queue.scheduled.is_empty());
+        assert_eq!(queue.get_tasks_for_dispatch().len(), 1);
+    }
+
+    #[test]
+    /// Due tasks still respect the webhook's mode
+    fn test_due_task_respects_mode() {
+        let mut queue = setup_queue("deploy");
+        queue.add_task(new_task(None));
+        queue.add_task(new_task(Some(Local::now() - Duration::seconds(1))));
+        queue.add_task(new_task(Some(Local::now() + Duration::hours(1))));
+        queue.scheduled.get_mut(&2).unwrap().run_at = Some(Local::now());
+
+        queue.queue_due_tasks();
+        assert!(queue.scheduled.is_empty());
+        assert_eq!(queue.queued.len(), 1);
+    }
+
+    #[test]
+    /// Scheduled and queued tasks can be cancelled
+    fn test_cancel_task() {
+        let mut queue = setup_queue("parallel");
+        queue.add_task(new_task(None));
+        queue.add_task(new_task(Some(Local::now() + Duration::hours(1))));
+
+        assert!(queue.cancel_task(1).is_some());
+        assert!(queue.cancel_task(2).is_some());
+        assert!(queue.cancel_task(3).is_none());
+        assert!(queue.queued.is_empty());
+        assert!(queue.scheduled.is_empty());
+        assert_eq!(*queue.queued_count.get("test").unwrap(), 0);
+    }
+
+    #[test]
+    /// Deploy mode coalesces new tasks into the queued one
+    fn test_deploy_coalesces() {
+        let mut queue = setup_queue("deploy");
+        assert_eq!(queue.add_task(new_task(None)), NewTaskResult::Added(1));
+        assert_eq!(queue.add_task(new_task(None)), NewTaskResult::Coalesced(1));
+    }
+
+    #[test]
+    /// Single mode coalesces new tasks into the running one
+    fn test_single_coalesces_running() {
+        let mut queue = setup_queue("single");
+        queue.add_task(new_task(None));
+        assert_eq!(queue.get_tasks_for_dispatch().len(), 1);
+        assert_eq!(queue.add_task(new_task(None)), NewTaskResult::Coalesced(1));
+    }
+
+    #[test]
+    /// New tasks are rejected, if the webhook's queue is full
+    fn test_webhook_max_queued() {
+        let mut queue = setup_queue("parallel");
+        queue.settings.webhooks[0].max_queued = Some(2);
+
+        assert_eq!(queue.add_task(new_task(None)), NewTaskResult::Added(1));
+        let run_at = Some(Local::now() + Duration::hours(1));
+        assert_eq!(queue.add_task(new_task(run_at)), NewTaskResult::Added(2));
+        assert_eq!(queue.add_task(new_task(None)), NewTaskResult::Rejected);
+    }
+
+    #[test]
+    /// New tasks are rejected, if the global queue is full
+    fn test_global_max_queued() {
+        let mut queue = setup_queue("parallel");
+        queue.settings.max_queued = Some(1);
+
+        assert_eq!(queue.add_task(new_task(None)), NewTaskResult::Added(1));
+        assert_eq!(queue.add_task(new_task(None)), NewTaskResult::Rejected);
+    }
+
+    #[test]
+    /// Retries are scheduled as new tasks, that reference the failed attempt
+    fn test_retry_task() {
+        let mut queue = setup_queue("parallel");
+        queue.add_task(new_task(None));
+        queue.get_tasks_for_dispatch();
+        let task = queue.finish_task(TaskCompleted {
+            webhook_name: "test".to_string(),
+            task_id: 1,
+            exit_code: 1,
+            stdout: String::new(),
+            stderr: String::new(),
+            step_results: Vec::new(),
+        });
+
+        assert_eq!(queue.retry_task(&task, Local::now()), 2);
+        let retry = queue.scheduled.get(&2).unwrap();
+        assert_eq!(retry.attempt, 2);
+        assert_eq!(retry.retry_of, Some(1));
+        assert!(queue.finished.contains_key(&1));
+    }
+
+    #[test]
+    /// Start and end times are stamped when tasks are dispatched and finished
+    fn test_task_times() {
+        let mut queue = setup_queue("parallel");
+        queue.add_task(new_task(None));
+        queue.get_tasks_for_dispatch();
+        assert!(queue.running.get(&1).unwrap().started_at.is_some());
+
+        let task = queue.finish_task(TaskCompleted {
+            webhook_name: "test".to_string(),
+            task_id: 1,
+            exit_code: 0,
+            stdout: String::new(),
+            stderr: String::new(),
+            step_results: Vec::new(),
+        });
+        assert!(task.finished_at.is_some());
+        assert!(task.duration.is_some());
+        assert!(task.queue_wait.is_some());
+
+        let statistics = queue.get_statistics(None);
+        let statistics = statistics.get("test").unwrap();
+        assert_eq!(statistics.runs, 1);
+        assert_eq!(statistics.success_rate, Some(1.0));
+    }
+
+    #[test]
+    /// Overviews can be restricted to the tasks of some webhooks
+    fn test_filter_webhooks() {
+        let mut queue = setup_queue("parallel");
+        queue.add_task(new_task(None));
+        queue.add_task(new_task(Some(Local::now() + Duration::hours(1))));
+
+        let all = vec!["test".to_string()];
+        let other = vec!["other".to_string()];
+        assert_eq!(queue.get_overview(10, Some(&all)).queued.len(), 1);
+        assert_eq!(queue.get_overview(10, None).scheduled.len(), 1);
+
+        let overview = queue.get_overview(10, Some(&other));
+        assert!(overview.queued.is_empty());
+        assert!(overview.scheduled.is_empty());
+        assert!(queue.get_statistics(Some(&other)).is_empty());
+
+        let filtered = queue.filter_webhooks(&other);
+        assert!(filtered.queued.is_empty());
+        assert!(filtered.scheduled.is_empty());
+        assert!(filtered.queued_count.is_empty());
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+
+use chrono::prelude::*;
+use serde::Serialize;
+
+use crate::task::task::Task;
+
+/// Aggregated statistics over all finished tasks of a webhook.
+/// Durations are in seconds.
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct WebhookStatistics {
+    pub runs: usize,
+    pub successes: usize,
+    pub success_rate: Option<f64>,
+    pub last_run: Option<DateTime<Local>>,
+    pub last_exit_code: Option<u32>,
+    pub average_duration: Option<f64>,
+    pub p50_duration: Option<f64>,
+    pub p95_duration: Option<f64>,
+    pub average_queue_wait: Option<f64>,
+}
+
+/// Compute the statistics of each webhook from its finished tasks
+pub fn get_statistics<'a>(
+    webhook_names: impl Iterator<Item = &'a String>,
+    finished: impl Iterator<Item = &'a Task> + Clone,
+) -> HashMap<String, WebhookStatistics> {
+    let mut statistics = HashMap::new();
+    for name in webhook_names {
+        let tasks: Vec<&Task> = finished
+            .clone()
+            .filter(|task| &task.webhook_name == name)
+            .collect();
+        statistics.insert(name.clone(), get_webhook_statistics(&tasks));
+    }
+
+    statistics
+}
+
+fn get_webhook_statistics(tasks: &[&Task]) -> WebhookStatistics {
+    if tasks.is_empty() {
+        return WebhookStatistics::default();
+    }
+
+    let runs = tasks.len();
+    let successes = tasks
+        .iter()
+        .filter(|task| task.exit_code == Some(0))
+        .count();
+    let last_task = tasks.iter().max_by_key(|task| task.finished_at).unwrap();
+
+    let mut durations: Vec<f64> = tasks.iter().filter_map(|task| task.duration).collect();
+    durations.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let waits: Vec<f64> = tasks.iter().filter_map(|task| task.queue_wait).collect();
+
+    WebhookStatistics {
+        runs,
+        successes,
+        success_rate: Some(successes as f64 / runs as f64),
+        last_run: last_task.finished_at,
+        last_exit_code: last_task.exit_code,
+        average_duration: average(&durations),
+        p50_duration: percentile(&durations, 50.0),
+        p95_duration: percentile(&durations, 95.0),
+        average_queue_wait: average(&waits),
+    }
+}
+
+fn average(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    Some(values.iter().sum::<f64>() / values.len() as f64)
+}
+
+/// Nearest-rank percentile of already sorted values
+fn percentile(sorted: &[f64], percentile: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.max(1) - 1])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Nearest-rank percentiles pick existing values
+    fn test_percentile() {
+        let values = vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0];
+        assert_eq!(percentile(&values, 50.0), Some(5.0));
+        assert_eq!(percentile(&values, 95.0), Some(10.0));
+        assert_eq!(percentile(&[], 95.0), None);
+    }
+}
@@ -0,0 +1,52 @@
+use std::collections::HashMap;
+
+use handlebars::RenderError;
+use serde::Serialize;
+
+use crate::settings::Step;
+use crate::task::template::render_template;
+
+/// A single step of a task's pipeline with its rendered command.
+#[derive(Serialize, Debug, Clone)]
+pub struct TaskStep {
+    pub name: String,
+    pub command: String,
+    pub cwd: String,
+    #[serde(skip_serializing)]
+    pub env: HashMap<String, String>,
+    pub timeout: Option<u64>,
+    pub continue_on_error: bool,
+}
+
+/// The outcome of an executed step.
+#[derive(Serialize, Debug, Clone)]
+pub struct StepResult {
+    pub name: String,
+    pub exit_code: u32,
+    pub stdout: String,
+    pub stderr: String,
+    /// The runtime of the step in seconds
+    pub duration: f64,
+}
+
+/// Render the commands of all configured steps with the given parameters.
+/// Steps without their own cwd inherit the cwd of the webhook.
+pub fn render_steps(
+    steps: &[Step],
+    cwd: &str,
+    parameters: &HashMap<String, String>,
+) -> Result<Vec<TaskStep>, RenderError> {
+    let mut task_steps = Vec::new();
+    for (index, step) in steps.iter().enumerate() {
+        task_steps.push(TaskStep {
+            name: step.name.clone().unwrap_or_else(|| (index + 1).to_string()),
+            command: render_template(&step.command, parameters)?,
+            cwd: step.cwd.clone().unwrap_or_else(|| cwd.to_string()),
+            env: step.env.clone(),
+            timeout: step.timeout,
+            continue_on_error: step.continue_on_error,
+        });
+    }
+
+    Ok(task_steps)
+}
@@ -1,19 +1,72 @@
 use ::serde::Serialize;
+use ::std::collections::HashMap;
+use ::std::path::PathBuf;
 use chrono::prelude::*;
 
 use crate::messages::NewTask;
+use crate::task::step::{StepResult, TaskStep};
+
+/// The commit, that triggered a task
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Commit {
+    /// The full name of the repository, e.g. `owner/repo`
+    pub repository: String,
+    pub sha: String,
+}
+
+impl Commit {
+    /// Check that the repository is a plain `owner/repo` name and the sha is a full
+    /// SHA-1 or SHA-256 hash, since both are taken from the request and used in API urls.
+    pub fn is_valid(&self) -> bool {
+        let is_name = |name: &str| {
+            !name.is_empty()
+                && name != "."
+                && name != ".."
+                && name
+                    .chars()
+                    .all(|character| character.is_ascii_alphanumeric() || "_.-".contains(character))
+        };
+        let repository_valid = self
+            .repository
+            .split_once('/')
+            .is_some_and(|(owner, repo)| is_name(owner) && is_name(repo));
+        let sha_valid = (self.sha.len() == 40 || self.sha.len() == 64)
+            && self
+                .sha
+                .chars()
+                .all(|character| character.is_ascii_hexdigit());
+
+        repository_valid && sha_valid
+    }
+}
 
 /// The Task is a simple struct to store all information about the state of a task.
 #[derive(Serialize, Debug, Clone)]
 pub struct Task {
     pub webhook_name: String,
     pub task_id: i32,
+    pub parameters: HashMap<String, String>,
     pub command: String,
     pub cwd: String,
+    pub steps: Vec<TaskStep>,
+    pub step_results: Vec<StepResult>,
     pub exit_code: Option<u32>,
     pub stdout: Option<String>,
     pub stderr: Option<String>,
     pub added_at: DateTime<Local>,
+    pub run_at: Option<DateTime<Local>>,
+    pub started_at: Option<DateTime<Local>>,
+    pub finished_at: Option<DateTime<Local>>,
+    /// The time in seconds the task waited in the queue, since it has been due
+    pub queue_wait: Option<f64>,
+    /// The runtime of the task in seconds
+    pub duration: Option<f64>,
+    /// The file the output is written to, if `log_dir` is configured
+    pub log_file: Option<PathBuf>,
+    pub attempt: u32,
+    pub retry_of: Option<i32>,
+    pub parent_id: Option<i32>,
+    pub commit: Option<Commit>,
 }
 
 impl Task {
@@ -21,12 +74,58 @@ impl Task {
         Task {
             webhook_name: new_task.webhook_name,
             task_id: id,
+            parameters: new_task.parameters,
             command: new_task.command,
             cwd: new_task.cwd,
+            steps: new_task.steps,
+            step_results: Vec::new(),
             exit_code: None,
             stdout: None,
             stderr: None,
             added_at: new_task.added_at,
+            run_at: new_task.run_at,
+            started_at: None,
+            finished_at: None,
+            queue_wait: None,
+            duration: None,
+            log_file: None,
+            attempt: 1,
+            retry_of: None,
+            parent_id: new_task.parent_id,
+            commit: new_task.commit,
+        }
+    }
+
+    /// The point in time since which the task could have been started
+    pub fn due_at(&self) -> DateTime<Local> {
+        self.run_at
+            .map_or(self.added_at, |run_at| run_at.max(self.added_at))
+    }
+
+    /// Create the next attempt of this (failed) task
+    pub fn retry(&self, id: i32, run_at: DateTime<Local>) -> Task {
+        Task {
+            webhook_name: self.webhook_name.clone(),
+            task_id: id,
+            parameters: self.parameters.clone(),
+            command: self.command.clone(),
+            cwd: self.cwd.clone(),
+            steps: self.steps.clone(),
+            step_results: Vec::new(),
+            exit_code: None,
+            stdout: None,
+            stderr: None,
+            added_at: Local::now(),
+            run_at: Some(run_at),
+            started_at: None,
+            finished_at: None,
+            queue_wait: None,
+            duration: None,
+            log_file: None,
+            attempt: self.attempt + 1,
+            retry_of: Some(self.task_id),
+            parent_id: self.parent_id,
+            commit: self.commit.clone(),
         }
     }
 }
@@ -0,0 +1,15 @@
+use std::collections::HashMap;
+
+use handlebars::{Handlebars, RenderError};
+
+/// Render a template with the given parameters.
+/// Strict mode is enabled to prevent missing or malformed arguments.
+pub fn render_template(
+    template: &str,
+    parameters: &HashMap<String, String>,
+) -> Result<String, RenderError> {
+    let mut handlebars = Handlebars::new();
+    handlebars.set_strict_mode(true);
+
+    handlebars.render_template(template, parameters)
+}
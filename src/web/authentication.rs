@@ -1,135 +1,376 @@
 use std::collections::HashMap;
 
 use actix_web::{http, HttpResponse};
-use base64;
-use hex;
-use hmac::{Hmac, Mac, NewMac};
-use log::warn;
-use sha1::Sha1;
+use chrono::Local;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
 
-use crate::settings::Settings;
+use crate::jwt::{claims_match, decode_jwt, is_jwt, Claims};
+use crate::password::verify_password;
+use crate::settings::{ApiToken, Provider, Scope, Settings};
+use crate::web::certificate::{find_client_certificate, PeerCertificate};
+use crate::web::provider::{get_proof, verify_proof, verify_timestamp};
 
-type HmacSha1 = Hmac<Sha1>;
+/// Information about a successfully authenticated request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Authenticated {
+    /// The authentication method(s) that have been used
+    pub method: String,
+    /// The authenticated user, if the method provides one
+    pub principal: Option<String>,
+    /// The claims of a JWT
+    pub claims: Claims,
+}
+
+impl Authenticated {
+    fn new(method: &str, principal: Option<String>) -> Self {
+        Authenticated {
+            method: method.to_string(),
+            principal,
+            claims: Claims::new(),
+        }
+    }
+}
 
+/// Check the credentials and the signature of a request.
+/// The provider determines the headers and the encoding of the signature.
+/// API tokens and JWTs additionally need the scope for the webhook of the request.
 pub fn verify_authentication_header(
     settings: &Settings,
+    provider: Provider,
     headers: &HashMap<String, String>,
-    body: &Vec<u8>,
-) -> Result<(), HttpResponse> {
-    // Extract the existing secret from the settings
-    let secret = settings.secret.clone().unwrap_or_default();
-    let has_secret = !secret.is_empty();
+    body: &[u8],
+    peer_certificate: Option<&PeerCertificate>,
+    scope: Scope,
+    webhook: Option<&str>,
+) -> Result<Authenticated, HttpResponse> {
+    // Check whether we have a secret. Expired secrets still require a signature.
+    let has_secret =
+        !settings.get_active_secrets(Local::now()).is_empty() || !settings.secrets.is_empty();
 
     // Check whether we have basic auth
     let user = settings.basic_auth_user.clone().unwrap_or_default();
     let password = settings.basic_auth_password.clone().unwrap_or_default();
-    let has_basic_auth = !user.is_empty() && !password.is_empty();
+    let has_basic_auth =
+        (!user.is_empty() && !password.is_empty()) || !settings.basic_auth_users.is_empty();
 
     // Check whether authentication is needed and whether we need both methods for authorization to work
-    let authentication_required = has_basic_auth || has_secret;
+    let authentication_required = has_basic_auth
+        || has_secret
+        || !settings.api_tokens.is_empty()
+        || !settings.client_certificates.is_empty()
+        || !settings.jwt_issuers.is_empty();
     let check_both = settings.basic_auth_and_secret;
 
     // We don't need any authentication, return early
     if !authentication_required {
-        return Ok(());
+        return Ok(Authenticated::new("none", None));
     }
 
-    let mut signature_valid = false;
+    // API tokens and JWTs replace basic auth.
+    // They still need a signature, if both authentication methods are required.
+    if let Some(token) = headers
+        .get("authorization")
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .map(str::trim)
+    {
+        let authenticated = if !settings.jwt_issuers.is_empty() && is_jwt(token) {
+            verify_jwt(settings, token)?
+        } else {
+            let api_token = verify_api_token(settings, token)?;
+            Authenticated::new("api_token", Some(api_token.name.clone()))
+        };
+        if check_both {
+            require_signature(settings, provider, headers, body)?;
+        }
+        verify_scope(settings, &authenticated, scope, webhook)?;
+        return Ok(authenticated);
+    }
 
-    // Check for a correct signature, if we have as secret or both authentication methods are required
-    if has_secret || check_both {
-        let signature = get_signature_header(headers)?;
-        if !signature.is_empty() {
-            verify_signature_header(signature, secret, body)?;
-            signature_valid = true;
-        } else if check_both {
-            // The signature header is required and couldn't be found
-            return Err(HttpResponse::Unauthorized().body("No signature header found"));
+    // Known client certificates replace basic auth as well.
+    // Webhooks may require a signature in addition to the certificate.
+    if let Some(certificate) = peer_certificate
+        .and_then(|peer| find_client_certificate(&settings.client_certificates, peer))
+    {
+        let authenticated =
+            Authenticated::new("client_certificate", Some(certificate.name.clone()));
+        let webhook_requires_signature = webhook
+            .and_then(|name| settings.webhooks.iter().find(|hook| hook.name == name))
+            .is_some_and(|hook| hook.client_certificate_and_secret);
+        if check_both || webhook_requires_signature {
+            require_signature(settings, provider, headers, body)?;
         }
+        verify_scope(settings, &authenticated, scope, webhook)?;
+        return Ok(authenticated);
     }
 
+    // Check for a correct signature, if we have as secret or both authentication methods are required
+    let signature_method = if check_both {
+        Some(require_signature(settings, provider, headers, body)?)
+    } else if has_secret {
+        verify_signature(settings, provider, headers, body)?
+    } else {
+        None
+    };
+
     // We only need one authentication method and the signature was valid
-    if !check_both && signature_valid {
+    if let (false, Some(method)) = (check_both, signature_method) {
+        return Ok(Authenticated::new(method, None));
+    }
+
+    let user = verify_basic_auth_header(headers, settings)?;
+
+    let authenticated = match signature_method {
+        Some(method) => Authenticated::new(&format!("{}+basic_auth", method), Some(user)),
+        None => Authenticated::new("basic_auth", Some(user)),
+    };
+    verify_scope(settings, &authenticated, scope, webhook)?;
+
+    Ok(authenticated)
+}
+
+/// Reject requests, that are only authenticated by a signature.
+/// Routes without a body can't rely on signatures, since the signature of an empty body
+/// never changes and can be replayed forever.
+pub fn require_credentials(authenticated: &Authenticated) -> Result<(), HttpResponse> {
+    if authenticated.method != "none" && authenticated.principal.is_none() {
+        warn!(
+            "Rejected request, that's only authenticated by {}",
+            authenticated.method
+        );
+        return Err(HttpResponse::Unauthorized().body("Credentials are required"));
+    }
+
+    Ok(())
+}
+
+/// Check the signature of the request against all active secrets.
+/// Returns the name of the signature method or `None`, if the request isn't signed.
+fn verify_signature(
+    settings: &Settings,
+    provider: Provider,
+    headers: &HashMap<String, String>,
+    body: &[u8],
+) -> Result<Option<&'static str>, HttpResponse> {
+    let proof = match get_proof(provider, headers)? {
+        Some(proof) => proof,
+        None => return Ok(None),
+    };
+
+    let secrets = settings.get_active_secrets(Local::now());
+    let secret_id = verify_proof(&proof, &secrets, body)?;
+    info!(secret = secret_id; "Got {} with secret {}", proof.method(), secret_id);
+    verify_timestamp(
+        &proof,
+        settings.signature_tolerance,
+        Local::now().timestamp(),
+    )?;
+
+    Ok(Some(proof.method()))
+}
+
+/// Check the signature of a request, that must be signed
+fn require_signature(
+    settings: &Settings,
+    provider: Provider,
+    headers: &HashMap<String, String>,
+    body: &[u8],
+) -> Result<&'static str, HttpResponse> {
+    match verify_signature(settings, provider, headers, body)? {
+        Some(method) => Ok(method),
+        // The signature header is required and couldn't be found
+        None => Err(HttpResponse::Unauthorized().body("No signature header found")),
+    }
+}
+
+/// Check that an authenticated API token or JWT grants the scope for the webhook.
+/// Users of `basic_auth_users` and `client_certificates` may be restricted to some webhooks,
+/// but have all scopes. All other authentication methods grant everything.
+pub fn verify_scope(
+    settings: &Settings,
+    authenticated: &Authenticated,
+    scope: Scope,
+    webhook: Option<&str>,
+) -> Result<(), HttpResponse> {
+    if authenticated.method == "jwt" {
+        return verify_jwt_scope(settings, authenticated, scope, webhook);
+    }
+
+    if authenticated.method != "api_token" {
+        if let Some(webhook) = webhook {
+            if !has_webhook_access(settings, authenticated, webhook) {
+                warn!(
+                    "{} isn't allowed to access webhook {}",
+                    authenticated.principal.as_deref().unwrap_or_default(),
+                    webhook
+                );
+                return Err(HttpResponse::Forbidden().body("Principal doesn't have access"));
+            }
+        }
         return Ok(());
     }
 
-    verify_basic_auth_header(headers, settings)?;
+    let allowed = settings
+        .api_tokens
+        .iter()
+        .find(|token| Some(&token.name) == authenticated.principal.as_ref())
+        .is_some_and(|token| token.allows(scope, webhook));
+    if !allowed {
+        warn!(
+            "API token {} doesn't grant {:?} for webhook {}",
+            authenticated.principal.as_deref().unwrap_or_default(),
+            scope,
+            webhook.unwrap_or("*")
+        );
+        return Err(HttpResponse::Forbidden().body("Token doesn't grant access"));
+    }
 
     Ok(())
 }
 
-/// Extract the correct signature header content from all headers
-/// It's possible to receive the signature from multiple Headers, since Github uses their own
-/// Header name for their signature method.
-fn get_signature_header(headers: &HashMap<String, String>) -> Result<String, HttpResponse> {
-    let mut header = headers.get("signature");
-    if header.is_none() {
-        header = headers.get("x-hub-signature");
+/// Get the webhooks, whose tasks the principal may see in the status, statistics and dashboard.
+/// Returns None, if it may see the tasks of all webhooks.
+pub fn get_visible_webhooks(
+    settings: &Settings,
+    authenticated: &Authenticated,
+) -> Option<Vec<String>> {
+    let visible: Vec<String> = settings
+        .webhooks
+        .iter()
+        .filter(|hook| has_webhook_access(settings, authenticated, &hook.name))
+        .map(|hook| hook.name.clone())
+        .collect();
+
+    if visible.len() == settings.webhooks.len() {
+        return None;
     }
+    Some(visible)
+}
 
-    // We dont' find any headers for signatures and this method is not required
-    let mut header = if let Some(header) = header {
-        header.clone()
-    } else {
-        return Ok("".to_string());
+/// Check whether the principal may access the webhook at all, regardless of the scope
+fn has_webhook_access(settings: &Settings, authenticated: &Authenticated, webhook: &str) -> bool {
+    let principal = authenticated.principal.as_ref();
+    let webhooks = match authenticated.method.as_str() {
+        "jwt" => return jwt_matches_webhook(settings, authenticated, webhook),
+        "api_token" => settings
+            .api_tokens
+            .iter()
+            .find(|token| Some(&token.name) == principal)
+            .map(|token| &token.webhooks),
+        "client_certificate" => settings
+            .client_certificates
+            .iter()
+            .find(|certificate| Some(&certificate.name) == principal)
+            .map(|certificate| &certificate.webhooks),
+        _ => settings
+            .basic_auth_users
+            .iter()
+            .find(|user| Some(&user.name) == principal)
+            .map(|user| &user.webhooks),
     };
 
-    // Header must be formatted like this: sha1={{hash}}
-    if !header.starts_with("sha1=") {
-        warn!("Got request with missing sha1= prefix");
-        Err(HttpResponse::Unauthorized()
-            .body("Error while parsing signature: Couldn't find prefix"))
-    } else {
-        Ok(header.split_off(5))
+    webhooks
+        .is_none_or(|webhooks| webhooks.is_empty() || webhooks.iter().any(|name| name == webhook))
+}
+
+/// Find the API token with the hash of the given token.
+/// All hashes are compared in constant time.
+fn verify_api_token<'a>(settings: &'a Settings, token: &str) -> Result<&'a ApiToken, HttpResponse> {
+    let hash = hex::encode(Sha256::digest(token.as_bytes()));
+
+    let mut found = None;
+    for api_token in settings.api_tokens.iter() {
+        if bool::from(
+            hash.as_bytes()
+                .ct_eq(api_token.hash.to_lowercase().as_bytes()),
+        ) {
+            found = Some(api_token);
+        }
     }
+
+    found.ok_or_else(|| {
+        warn!("Got request with invalid API token");
+        HttpResponse::Unauthorized().body("Invalid token")
+    })
 }
 
-/// Verify the signature header. Checks our own signature generated by hmac sha1 with secret and payload
-/// against the signature provided in the header.
-fn verify_signature_header(
-    signature: String,
-    secret: String,
-    body: &Vec<u8>,
-) -> Result<(), HttpResponse> {
-    // Try to decode the sha1 into bytes. Should be a valid hex string
-    let signature_bytes = match hex::decode(&signature) {
-        Ok(result) => result,
+/// Verify a JWT against the keys of its issuer.
+/// The subject of the token is used as principal.
+fn verify_jwt(settings: &Settings, token: &str) -> Result<Authenticated, HttpResponse> {
+    let (issuer, claims) = match decode_jwt(&settings.jwt_issuers, token) {
+        Ok(decoded) => decoded,
         Err(error) => {
-            warn!("Error decoding signature: {}, {}", signature, error);
-            return Err(HttpResponse::Unauthorized().body("Invalid sha1 signature"));
+            warn!("Got request with invalid JWT: {}", error);
+            return Err(HttpResponse::Unauthorized().body("Invalid token"));
         }
     };
+    if !claims_match(&issuer.claims, &claims) {
+        warn!(
+            "Got JWT of issuer {}, that doesn't have the required claims",
+            issuer.issuer
+        );
+        return Err(HttpResponse::Unauthorized().body("Invalid token"));
+    }
 
-    // Generate the own hmac sha1 from the secret and body and verify that it's identical to the signature
-    let secret_bytes = secret.into_bytes();
-    let expected_signature = generate_signature_sha1(&secret_bytes, body);
-
-    match expected_signature.clone().verify(&signature_bytes) {
-        Ok(()) => Ok(()),
-        Err(_) => {
-            warn!(
-                "Our sha1: {}",
-                hex::encode(expected_signature.finalize().into_bytes())
-            );
-            warn!("Got wrong sha1: {}", signature);
-            Err(HttpResponse::Unauthorized().body("Invalid sha1 signature"))
-        }
+    let subject = claims
+        .get("sub")
+        .and_then(|subject| subject.as_str())
+        .unwrap_or(&issuer.issuer)
+        .to_string();
+    let mut authenticated = Authenticated::new("jwt", Some(subject));
+    authenticated.claims = claims;
+
+    Ok(authenticated)
+}
+
+/// Check the scopes of the token's issuer and the `jwt_claims` of the webhook
+fn verify_jwt_scope(
+    settings: &Settings,
+    authenticated: &Authenticated,
+    scope: Scope,
+    webhook: Option<&str>,
+) -> Result<(), HttpResponse> {
+    let issuer = authenticated.claims.get("iss").and_then(|iss| iss.as_str());
+    let has_scope = settings
+        .jwt_issuers
+        .iter()
+        .find(|jwt_issuer| Some(jwt_issuer.issuer.as_str()) == issuer)
+        .is_some_and(|jwt_issuer| {
+            jwt_issuer.scopes.contains(&Scope::Admin) || jwt_issuer.scopes.contains(&scope)
+        });
+    let has_webhook =
+        webhook.is_none_or(|webhook| jwt_matches_webhook(settings, authenticated, webhook));
+
+    if !has_scope || !has_webhook {
+        warn!(
+            "JWT of {} doesn't grant {:?} for webhook {}",
+            authenticated.principal.as_deref().unwrap_or_default(),
+            scope,
+            webhook.unwrap_or("*")
+        );
+        return Err(HttpResponse::Forbidden().body("Token doesn't grant access"));
     }
+
+    Ok(())
 }
 
-/// Create a hmac SHA1 instance from a secret and body
-fn generate_signature_sha1(secret_bytes: &Vec<u8>, body: &Vec<u8>) -> HmacSha1 {
-    let mut hmac =
-        HmacSha1::new_from_slice(secret_bytes).expect("Couldn't create hmac with current secret");
-    hmac.update(body);
-    hmac
+/// Check whether the claims of the token match the `jwt_claims` of the webhook
+fn jwt_matches_webhook(settings: &Settings, authenticated: &Authenticated, webhook: &str) -> bool {
+    settings
+        .webhooks
+        .iter()
+        .find(|hook| hook.name == webhook)
+        .is_some_and(|hook| {
+            !hook.jwt_claims.is_empty() && claims_match(&hook.jwt_claims, &authenticated.claims)
+        })
 }
 
-// Verify the basic_auth header
+// Verify the basic_auth header and return the authenticated user
 fn verify_basic_auth_header(
     headers: &HashMap<String, String>,
     settings: &Settings,
-) -> Result<(), HttpResponse> {
+) -> Result<String, HttpResponse> {
     let header = headers.get("authorization");
     // Check whether we can find a Basic Auth header. It's required at this point
     let mut header = if let Some(header) = header {
@@ -163,32 +404,39 @@ fn verify_basic_auth_header(
         return Err(HttpResponse::Unauthorized().body("Invalid utf8 token"));
     };
 
-    let credentials: Vec<&str> = token.split(':').collect();
-    if credentials.len() != 2 {
+    // The user must not contain colons, but the password may
+    let (name, password) = if let Some(credentials) = token.split_once(':') {
+        credentials
+    } else {
         warn!("Got request with malformed credential string");
         return Err(HttpResponse::Unauthorized().body("Malformed credential string"));
-    }
-
-    // Ensure user is set in config
-    let user = if let Some(user) = &settings.basic_auth_user {
-        user
-    } else {
-        return Err(get_autorization_request());
     };
 
-    // Ensure password is set in config
-    let password = if let Some(password) = &settings.basic_auth_password {
-        password
-    } else {
+    // Users with hashed passwords
+    if let Some(user) = settings
+        .basic_auth_users
+        .iter()
+        .find(|user| user.name == name)
+    {
+        if verify_password(&user.password_hash, password) {
+            return Ok(user.name.clone());
+        }
+        warn!("Got invalid password for user {}", name);
         return Err(get_autorization_request());
-    };
+    }
 
-    if user != credentials[0] || password != credentials[1] {
-        warn!("Got invalid base64 credentials");
-        return Err(get_autorization_request());
+    // The single user with a plaintext password
+    if let (Some(user), Some(expected)) = (&settings.basic_auth_user, &settings.basic_auth_password)
+    {
+        let user_valid: bool = user.as_bytes().ct_eq(name.as_bytes()).into();
+        let password_valid: bool = expected.as_bytes().ct_eq(password.as_bytes()).into();
+        if user_valid && password_valid {
+            return Ok(user.clone());
+        }
     }
 
-    Ok(())
+    warn!("Got invalid base64 credentials");
+    Err(get_autorization_request())
 }
 
 fn get_autorization_request() -> HttpResponse {
@@ -200,6 +448,12 @@ fn get_autorization_request() -> HttpResponse {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::jwt::tests::{PRIVATE_KEY, PUBLIC_KEY};
+    use crate::jwt::JwtKey;
+    use crate::settings::{BasicAuthUser, ClientCertificate, JwtIssuer, Secret};
+    use crate::web::provider::generate_signature_sha1;
+    use hmac::Mac;
+    use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header};
 
     fn setup_args() -> (Settings, HashMap<String, String>, Vec<u8>) {
         let settings = Settings {
@@ -207,11 +461,35 @@ mod tests {
             port: 8000,
             ssl_private_key: None,
             ssl_cert_chain: None,
+            ssl_client_ca: None,
+            ssl_client_cert_required: false,
+            client_certificates: Vec::new(),
             secret: Some("A secret string".to_string()),
+            secrets: Vec::new(),
             basic_auth_user: None,
             basic_auth_password: None,
             basic_auth_and_secret: false,
+            basic_auth_users: Vec::new(),
+            signature_tolerance: 300,
+            replay_window: None,
+            allowed_ips: Vec::new(),
+            trusted_proxies: Vec::new(),
+            lockout_threshold: 0,
+            lockout_seconds: 60,
+            lockout_max_seconds: 3600,
             workers: 8,
+            max_queued: None,
+            health_path: "/healthz".to_string(),
+            ready_path: "/readyz".to_string(),
+            ready_stall_timeout: 3600,
+            log_level: "info".to_string(),
+            log_format: "human".to_string(),
+            log_dir: None,
+            audit_log: None,
+            notification_log: None,
+            smtp: None,
+            api_tokens: Vec::new(),
+            jwt_issuers: Vec::new(),
             webhooks: Vec::new(),
         };
 
@@ -227,7 +505,7 @@ mod tests {
     fn add_signature_header(
         settings: &Settings,
         headers: &mut HashMap<String, String>,
-        body: &Vec<u8>,
+        body: &[u8],
     ) {
         let hmac = generate_signature_sha1(&settings.secret.clone().unwrap().into_bytes(), body);
         let prefix = "sha1=".to_string();
@@ -238,10 +516,10 @@ mod tests {
     }
 
     fn add_basic_auth_header(headers: &mut HashMap<String, String>) {
-        let basic_header = format!("TestUser:TestPassword").into_bytes();
+        let basic_header = "TestUser:TestPassword".as_bytes();
         headers.insert(
             "authorization".to_string(),
-            "Basic ".to_string() + &base64::encode(&basic_header),
+            "Basic ".to_string() + &base64::encode(basic_header),
         );
     }
 
@@ -255,7 +533,57 @@ mod tests {
     fn test_valid_signature() {
         let (settings, mut headers, body) = setup_args();
         add_signature_header(&settings, &mut headers, &body);
-        assert!(verify_authentication_header(&settings, &headers, &body).is_ok());
+        assert!(verify_authentication_header(
+            &settings,
+            Provider::Generic,
+            &headers,
+            &body,
+            None,
+            Scope::Trigger,
+            None
+        )
+        .is_ok());
+    }
+
+    #[test]
+    /// Signatures of all secrets are accepted until they expire
+    fn test_rotated_secrets() {
+        let (mut settings, mut headers, body) = setup_args();
+        add_signature_header(&settings, &mut headers, &body);
+        let old = settings.secret.take().unwrap();
+        let verify = |settings: &Settings| {
+            verify_authentication_header(
+                settings,
+                Provider::Generic,
+                &headers,
+                &body,
+                None,
+                Scope::Trigger,
+                None,
+            )
+        };
+
+        settings.secrets = vec![
+            Secret {
+                id: "new".to_string(),
+                secret: "A new secret".to_string(),
+                expires: None,
+            },
+            Secret {
+                id: "old".to_string(),
+                secret: old,
+                expires: Some(Local::now() + chrono::Duration::days(1)),
+            },
+        ];
+        assert!(verify(&settings).is_ok());
+
+        // Expired secrets aren't accepted anymore
+        settings.secrets[1].expires = Some(Local::now() - chrono::Duration::days(1));
+        assert!(verify(&settings).is_err());
+
+        // Signatures are still required, if all secrets have expired
+        settings.secrets.remove(0);
+        assert!(verify(&settings).is_err());
     }
 
     #[test]
@@ -265,14 +593,32 @@ mod tests {
         add_signature_header(&settings, &mut headers, &body);
         let signature = headers.remove("signature").unwrap();
         headers.insert("x-hub-signature".to_string(), signature);
-        assert!(verify_authentication_header(&settings, &headers, &body).is_ok());
+        assert!(verify_authentication_header(
+            &settings,
+            Provider::Generic,
+            &headers,
+            &body,
+            None,
+            Scope::Trigger,
+            None
+        )
+        .is_ok());
     }
 
     #[test]
     /// Requests fail if signature authentication is required, but no header is specified
     fn test_no_signature() {
         let (settings, headers, body) = setup_args();
-        assert!(verify_authentication_header(&settings, &headers, &body).is_err());
+        assert!(verify_authentication_header(
+            &settings,
+            Provider::Generic,
+            &headers,
+            &body,
+            None,
+            Scope::Trigger,
+            None
+        )
+        .is_err());
     }
 
     #[test]
@@ -283,7 +629,46 @@ mod tests {
             "signature".to_string(),
             "sha1=a68ccdf08e2767a8307c8cda67a77f4046cb9e17".to_string(),
         );
-        assert!(verify_authentication_header(&settings, &headers, &body).is_err());
+        assert!(verify_authentication_header(
+            &settings,
+            Provider::Generic,
+            &headers,
+            &body,
+            None,
+            Scope::Trigger,
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    /// Signatures alone aren't enough for routes without a body
+    fn test_require_credentials() {
+        let (mut settings, mut headers, _) = setup_args();
+        add_signature_header(&settings, &mut headers, &[]);
+        let verify = |settings: &Settings, headers: &HashMap<String, String>| {
+            verify_authentication_header(
+                settings,
+                Provider::Generic,
+                headers,
+                &[],
+                None,
+                Scope::Cancel,
+                None,
+            )
+            .unwrap()
+        };
+        assert!(require_credentials(&verify(&settings, &headers)).is_err());
+
+        populate_base_auth_credentials(&mut settings);
+        add_basic_auth_header(&mut headers);
+        headers.remove("signature");
+        assert!(require_credentials(&verify(&settings, &headers)).is_ok());
+
+        // Without any authentication, there's nothing to require
+        settings = setup_args().0;
+        settings.secret = None;
+        assert!(require_credentials(&verify(&settings, &HashMap::new())).is_ok());
     }
 
     #[test]
@@ -293,7 +678,18 @@ mod tests {
         populate_base_auth_credentials(&mut settings);
 
         add_basic_auth_header(&mut headers);
-        assert!(verify_authentication_header(&settings, &headers, &body).is_ok());
+        let authenticated = verify_authentication_header(
+            &settings,
+            Provider::Generic,
+            &headers,
+            &body,
+            None,
+            Scope::Trigger,
+            None,
+        )
+        .unwrap();
+        assert_eq!(authenticated.method, "basic_auth");
+        assert_eq!(authenticated.principal, Some("TestUser".to_string()));
     }
 
     #[test]
@@ -307,7 +703,16 @@ mod tests {
             "authorization".to_string(),
             "Basic cm9mbDpyb2Zs".to_string(),
         );
-        assert!(verify_authentication_header(&settings, &headers, &body).is_err());
+        assert!(verify_authentication_header(
+            &settings,
+            Provider::Generic,
+            &headers,
+            &body,
+            None,
+            Scope::Trigger,
+            None
+        )
+        .is_err());
     }
 
     #[test]
@@ -319,7 +724,16 @@ mod tests {
 
         add_basic_auth_header(&mut headers);
         add_signature_header(&settings, &mut headers, &body);
-        assert!(verify_authentication_header(&settings, &headers, &body).is_ok());
+        assert!(verify_authentication_header(
+            &settings,
+            Provider::Generic,
+            &headers,
+            &body,
+            None,
+            Scope::Trigger,
+            None
+        )
+        .is_ok());
     }
 
     #[test]
@@ -330,7 +744,16 @@ mod tests {
         populate_base_auth_credentials(&mut settings);
 
         add_signature_header(&settings, &mut headers, &body);
-        assert!(verify_authentication_header(&settings, &headers, &body).is_err());
+        assert!(verify_authentication_header(
+            &settings,
+            Provider::Generic,
+            &headers,
+            &body,
+            None,
+            Scope::Trigger,
+            None
+        )
+        .is_err());
     }
 
     #[test]
@@ -341,6 +764,303 @@ mod tests {
         populate_base_auth_credentials(&mut settings);
 
         add_basic_auth_header(&mut headers);
-        assert!(verify_authentication_header(&settings, &headers, &body).is_err());
+        assert!(verify_authentication_header(
+            &settings,
+            Provider::Generic,
+            &headers,
+            &body,
+            None,
+            Scope::Trigger,
+            None
+        )
+        .is_err());
+    }
+
+    #[test]
+    /// API tokens are checked against their hash, scopes and webhooks
+    fn test_api_token() {
+        let (mut settings, mut headers, body) = setup_args();
+        settings.api_tokens.push(ApiToken {
+            name: "ci".to_string(),
+            hash: hex::encode(Sha256::digest(b"token")),
+            webhooks: vec!["deploy".to_string()],
+            scopes: vec![Scope::Trigger],
+        });
+        headers.insert("authorization".to_string(), "Bearer token".to_string());
+
+        let verify = |headers: &HashMap<String, String>, scope, webhook| {
+            verify_authentication_header(
+                &settings,
+                Provider::Generic,
+                headers,
+                &body,
+                None,
+                scope,
+                webhook,
+            )
+        };
+        let authenticated = verify(&headers, Scope::Trigger, Some("deploy")).unwrap();
+        assert_eq!(authenticated.method, "api_token");
+        assert_eq!(authenticated.principal, Some("ci".to_string()));
+
+        assert!(verify(&headers, Scope::Trigger, Some("other")).is_err());
+        assert!(verify(&headers, Scope::Cancel, None).is_err());
+
+        headers.insert("authorization".to_string(), "Bearer wrong".to_string());
+        assert!(verify(&headers, Scope::Trigger, Some("deploy")).is_err());
+    }
+
+    #[test]
+    /// API tokens need a signature as well, if both methods are required
+    fn test_both_required_api_token() {
+        let (mut settings, mut headers, body) = setup_args();
+        settings.basic_auth_and_secret = true;
+        populate_base_auth_credentials(&mut settings);
+        settings.api_tokens.push(ApiToken {
+            name: "ci".to_string(),
+            hash: hex::encode(Sha256::digest(b"token")),
+            webhooks: Vec::new(),
+            scopes: vec![Scope::Trigger],
+        });
+        headers.insert("authorization".to_string(), "Bearer token".to_string());
+
+        let verify = |headers: &HashMap<String, String>| {
+            verify_authentication_header(
+                &settings,
+                Provider::Generic,
+                headers,
+                &body,
+                None,
+                Scope::Trigger,
+                None,
+            )
+        };
+        assert!(verify(&headers).is_err());
+
+        add_signature_header(&settings, &mut headers, &body);
+        assert_eq!(verify(&headers).unwrap().method, "api_token");
+    }
+
+    #[test]
+    /// Client certificates may be restricted to some webhooks and may need a signature
+    fn test_client_certificate() {
+        let (mut settings, mut headers, body) = setup_args();
+        settings.client_certificates.push(ClientCertificate {
+            name: "build-server".to_string(),
+            common_name: Some("build.example.org".to_string()),
+            san: None,
+            webhooks: vec!["deploy".to_string(), "signed".to_string()],
+        });
+        settings.webhooks = serde_yaml::from_str(
+            "
+- {name: deploy, cwd: /tmp, command: deploy}
+- {name: signed, cwd: /tmp, command: deploy, client_certificate_and_secret: true}
+- {name: other, cwd: /tmp, command: other}
+",
+        )
+        .unwrap();
+        let peer = PeerCertificate {
+            common_name: Some("build.example.org".to_string()),
+            sans: Vec::new(),
+        };
+
+        let verify = |headers: &HashMap<String, String>, webhook| {
+            verify_authentication_header(
+                &settings,
+                Provider::Generic,
+                headers,
+                &body,
+                Some(&peer),
+                Scope::Trigger,
+                Some(webhook),
+            )
+        };
+        let authenticated = verify(&headers, "deploy").unwrap();
+        assert_eq!(authenticated.method, "client_certificate");
+        assert_eq!(authenticated.principal, Some("build-server".to_string()));
+
+        // The principal isn't allowed to access webhooks outside of its list
+        let response = verify_scope(&settings, &authenticated, Scope::Trigger, Some("other"));
+        assert_eq!(response.unwrap_err().status(), http::StatusCode::FORBIDDEN);
+
+        // The webhook requires a signature in addition to the certificate
+        assert!(verify(&headers, "signed").is_err());
+        add_signature_header(&settings, &mut headers, &body);
+        assert!(verify(&headers, "signed").is_ok());
+    }
+
+    #[test]
+    /// JWTs need the scope and the `jwt_claims` of the webhook
+    fn test_jwt() {
+        let (mut settings, mut headers, body) = setup_args();
+        settings.jwt_issuers.push(JwtIssuer {
+            issuer: "https://ci.example.org".to_string(),
+            audience: "webhook-server".to_string(),
+            jwks_file: None,
+            public_keys: Vec::new(),
+            claims: HashMap::new(),
+            scopes: vec![Scope::Trigger],
+            keys: vec![JwtKey {
+                id: None,
+                key: DecodingKey::from_ed_pem(PUBLIC_KEY.as_bytes()).unwrap(),
+            }],
+        });
+        settings.webhooks = serde_yaml::from_str(
+            "
+- {name: deploy, cwd: /tmp, command: deploy, jwt_claims: {repository: org/app}}
+- {name: other, cwd: /tmp, command: other}
+",
+        )
+        .unwrap();
+        let key = EncodingKey::from_ed_pem(PRIVATE_KEY.as_bytes()).unwrap();
+        let encode = |repository: &str| {
+            let claims = serde_json::json!({
+                "iss": "https://ci.example.org",
+                "aud": "webhook-server",
+                "sub": "repo:org/app",
+                "repository": repository,
+                "exp": Local::now().timestamp() + 300,
+            });
+            let token = jsonwebtoken::encode(&Header::new(Algorithm::EdDSA), &claims, &key);
+            format!("Bearer {}", token.unwrap())
+        };
+
+        let verify = |headers: &HashMap<String, String>, scope, webhook| {
+            verify_authentication_header(
+                &settings,
+                Provider::Generic,
+                headers,
+                &body,
+                None,
+                scope,
+                webhook,
+            )
+        };
+        headers.insert("authorization".to_string(), encode("org/app"));
+        let authenticated = verify(&headers, Scope::Trigger, Some("deploy")).unwrap();
+        assert_eq!(authenticated.method, "jwt");
+        assert_eq!(authenticated.principal, Some("repo:org/app".to_string()));
+
+        // Tokens can't trigger webhooks with empty `jwt_claims`
+        let response = verify(&headers, Scope::Trigger, Some("other")).unwrap_err();
+        assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+
+        // The issuer doesn't grant other scopes
+        let response = verify(&headers, Scope::Cancel, None).unwrap_err();
+        assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+
+        // The claims need to match the `jwt_claims` of the webhook
+        headers.insert("authorization".to_string(), encode("org/other"));
+        let response = verify(&headers, Scope::Trigger, Some("deploy")).unwrap_err();
+        assert_eq!(response.status(), http::StatusCode::FORBIDDEN);
+
+        // API tokens with dots aren't mistaken for JWTs
+        settings.api_tokens.push(ApiToken {
+            name: "ci".to_string(),
+            hash: hex::encode(Sha256::digest(b"my.api.token")),
+            webhooks: Vec::new(),
+            scopes: vec![Scope::Trigger],
+        });
+        headers.insert(
+            "authorization".to_string(),
+            "Bearer my.api.token ".to_string(),
+        );
+        let authenticated = verify_authentication_header(
+            &settings,
+            Provider::Generic,
+            &headers,
+            &body,
+            None,
+            Scope::Trigger,
+            Some("deploy"),
+        )
+        .unwrap();
+        assert_eq!(authenticated.method, "api_token");
+    }
+
+    #[test]
+    /// Users with hashed passwords may be restricted to some webhooks
+    fn test_basic_auth_users() {
+        let (mut settings, mut headers, body) = setup_args();
+        settings.secret = None;
+        settings.basic_auth_users.push(BasicAuthUser {
+            name: "ci".to_string(),
+            password_hash: bcrypt::hash("pass:word", 4).unwrap(),
+            webhooks: vec!["deploy".to_string()],
+        });
+        headers.insert(
+            "authorization".to_string(),
+            "Basic ".to_string() + &base64::encode("ci:pass:word"),
+        );
+
+        let verify = |headers: &HashMap<String, String>, webhook| {
+            verify_authentication_header(
+                &settings,
+                Provider::Generic,
+                headers,
+                &body,
+                None,
+                Scope::Trigger,
+                webhook,
+            )
+        };
+        let authenticated = verify(&headers, Some("deploy")).unwrap();
+        assert_eq!(authenticated.principal, Some("ci".to_string()));
+        assert!(verify(&headers, Some("other")).is_err());
+
+        headers.insert(
+            "authorization".to_string(),
+            "Basic ".to_string() + &base64::encode("ci:wrong"),
+        );
+        assert!(verify(&headers, Some("deploy")).is_err());
+    }
+
+    #[test]
+    /// Restricted principals only see the tasks of their webhooks in overviews
+    fn test_visible_webhooks() {
+        let (mut settings, _, _) = setup_args();
+        settings.webhooks = serde_yaml::from_str(
+            "
+- {name: deploy, cwd: /tmp, command: deploy}
+- {name: other, cwd: /tmp, command: other}
+",
+        )
+        .unwrap();
+        settings.basic_auth_users.push(BasicAuthUser {
+            name: "ci".to_string(),
+            password_hash: String::new(),
+            webhooks: vec!["deploy".to_string()],
+        });
+        settings.api_tokens.push(ApiToken {
+            name: "monitoring".to_string(),
+            hash: String::new(),
+            webhooks: vec!["other".to_string()],
+            scopes: vec![Scope::Read],
+        });
+        let authenticated = |method: &str, principal: Option<&str>| {
+            Authenticated::new(method, principal.map(str::to_string))
+        };
+
+        assert_eq!(
+            get_visible_webhooks(&settings, &authenticated("basic_auth", Some("ci"))),
+            Some(vec!["deploy".to_string()])
+        );
+        assert_eq!(
+            get_visible_webhooks(&settings, &authenticated("api_token", Some("monitoring"))),
+            Some(vec!["other".to_string()])
+        );
+        assert_eq!(
+            get_visible_webhooks(&settings, &authenticated("basic_auth", Some("admin"))),
+            None
+        );
+        assert_eq!(
+            get_visible_webhooks(&settings, &authenticated("signature", None)),
+            None
+        );
+        // Tokens only match webhooks with `jwt_claims`
+        assert_eq!(
+            get_visible_webhooks(&settings, &authenticated("jwt", Some("repo:org/app"))),
+            Some(Vec::new())
+        );
     }
 }
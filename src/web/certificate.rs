@@ -0,0 +1,162 @@
+use std::any::Any;
+
+use actix_tls::accept::rustls::TlsStream;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use actix_web::HttpRequest;
+use log::warn;
+use x509_parser::prelude::*;
+
+use crate::settings::ClientCertificate;
+
+/// The identity of the verified certificate, that the client presented during the handshake
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PeerCertificate {
+    pub common_name: Option<String>,
+    /// DNS names, emails and URIs of the subject alternative names
+    pub sans: Vec<String>,
+}
+
+/// Store the certificate of TLS connections for the requests of the connection.
+/// Rustls already verified the certificate against the `ssl_client_ca`.
+pub fn store_peer_certificate(connection: &dyn Any, data: &mut Extensions) {
+    let stream = match connection.downcast_ref::<TlsStream<TcpStream>>() {
+        Some(stream) => stream,
+        None => return,
+    };
+
+    let (_, session) = stream.get_ref();
+    if let Some(certificate) = session
+        .peer_certificates()
+        .and_then(|chain| chain.first())
+        .and_then(|certificate| parse_certificate(&certificate.0))
+    {
+        data.insert(certificate);
+    }
+}
+
+/// Get the certificate of the connection of a request
+pub fn get_peer_certificate(request: &HttpRequest) -> Option<PeerCertificate> {
+    request.extensions().get::<PeerCertificate>().cloned()
+}
+
+/// Find the principal of a peer certificate.
+/// If both the common name and the san are configured, both need to match.
+pub fn find_client_certificate<'a>(
+    certificates: &'a [ClientCertificate],
+    peer: &PeerCertificate,
+) -> Option<&'a ClientCertificate> {
+    certificates.iter().find(|certificate| {
+        let common_name_matches = certificate
+            .common_name
+            .as_ref()
+            .is_none_or(|name| peer.common_name.as_ref() == Some(name));
+        let san_matches = certificate
+            .san
+            .as_ref()
+            .is_none_or(|san| peer.sans.contains(san));
+
+        common_name_matches && san_matches
+    })
+}
+
+fn parse_certificate(der: &[u8]) -> Option<PeerCertificate> {
+    let (_, certificate) = match X509Certificate::from_der(der) {
+        Ok(certificate) => certificate,
+        Err(error) => {
+            warn!("Couldn't parse client certificate: {}", error);
+            return None;
+        }
+    };
+
+    let common_name = certificate
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|name| name.as_str().ok())
+        .map(|name| name.to_string());
+
+    let mut sans = Vec::new();
+    if let Ok(Some(extension)) = certificate.subject_alternative_name() {
+        for name in extension.value.general_names.iter() {
+            match name {
+                GeneralName::DNSName(name)
+                | GeneralName::RFC822Name(name)
+                | GeneralName::URI(name) => sans.push(name.to_string()),
+                _ => {}
+            }
+        }
+    }
+
+    Some(PeerCertificate { common_name, sans })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Self-signed certificate of `ci.example.org` with a dns and an email san
+    const CERTIFICATE: [&str; 8] = [
+        "MIIBuzCCAWCgAwIBAgIUNWnQgkOYWwiXo6Yu462zQn+KEm8wCgYIKoZIzj0EAwIwGTEXMBUGA1UEAwwO",
+        "Y2kuZXhhbXBsZS5vcmcwIBcNMjYxMDE5MDQwNDU1WhgPMjEyNjA5MjUwNDA0NTVaMBkxFzAVBgNVBAMM",
+        "DmNpLmV4YW1wbGUub3JnMFkwEwYHKoZIzj0CAQYIKoZIzj0DAQcDQgAES7XqmLx4ei2NwiVayrASoJSQ",
+        "s71wm6UpsEXBPnCbDOWyq0DxDpyQ7b2RvimlzzYtpXKIfKrGw9hyWJRCWoJrPKOBgzCBgDAdBgNVHQ4E",
+        "FgQU/F1ktZUNvovE905vXisk00H/lKMwHwYDVR0jBBgwFoAU/F1ktZUNvovE905vXisk00H/lKMwDwYD",
+        "VR0TAQH/BAUwAwEB/zAtBgNVHREEJjAkghJkZXBsb3kuZXhhbXBsZS5vcmeBDmNpQGV4YW1wbGUub3Jn",
+        "MAoGCCqGSM49BAMCA0kAMEYCIQC/MBc2LzmNmMap28sBdcNb+l0w2jXTAeUwzlfBrcCaeQIhALWtjGrW",
+        "urAuyxSL6zmylIZb4rM410Q+SrfefQ4JAEaN",
+    ];
+
+    #[test]
+    /// The common name and the alternative names are extracted
+    fn test_parse_certificate() {
+        let der = base64::decode(CERTIFICATE.concat()).unwrap();
+        let certificate = parse_certificate(&der).unwrap();
+        assert_eq!(certificate.common_name, Some("ci.example.org".to_string()));
+        assert_eq!(
+            certificate.sans,
+            vec![
+                "deploy.example.org".to_string(),
+                "ci@example.org".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    /// If both the common name and the san are configured, both need to match
+    fn test_find_client_certificate() {
+        let certificates = vec![
+            ClientCertificate {
+                name: "both".to_string(),
+                common_name: Some("ci.example.org".to_string()),
+                san: Some("deploy.example.org".to_string()),
+                webhooks: Vec::new(),
+            },
+            ClientCertificate {
+                name: "san".to_string(),
+                common_name: None,
+                san: Some("ci@example.org".to_string()),
+                webhooks: Vec::new(),
+            },
+        ];
+        let find = |common_name: &str, sans: &[&str]| {
+            let peer = PeerCertificate {
+                common_name: Some(common_name.to_string()),
+                sans: sans.iter().map(|san| san.to_string()).collect(),
+            };
+            find_client_certificate(&certificates, &peer)
+                .map(|certificate| certificate.name.clone())
+        };
+
+        assert_eq!(
+            find("ci.example.org", &["deploy.example.org"]),
+            Some("both".to_string())
+        );
+        assert_eq!(find("ci.example.org", &[]), None);
+        assert_eq!(find("other.example.org", &["deploy.example.org"]), None);
+        assert_eq!(
+            find("other.example.org", &["ci@example.org"]),
+            Some("san".to_string())
+        );
+    }
+}
@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+
+use actix_web::*;
+use actix_web::{http, HttpResponse};
+use chrono::prelude::*;
+use handlebars::Handlebars;
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::audit::{write_audit_entry, AuditEntry, Decision};
+use crate::messages::{CancelTask, GetTask, GetTasks, NewTaskResult};
+use crate::settings::{Provider, Scope};
+use crate::task::task::Task;
+use crate::web::authentication::{
+    get_visible_webhooks, require_credentials, verify_authentication_header, Authenticated,
+};
+use crate::web::certificate::get_peer_certificate;
+use crate::web::helper::*;
+use crate::web::network::{get_webhook_allowed_ips, verify_request_ip};
+use crate::web::throttle::{authenticate, verify_rate_limit};
+use crate::web::{AppState, Payload};
+
+/// The amount of finished tasks shown on the dashboard
+const FINISHED_LIMIT: usize = 50;
+
+/// The dashboard reloads itself after this amount of seconds
+const REFRESH_SECONDS: u32 = 10;
+
+const HEADER_TEMPLATE: &str = include_str!("templates/header.hbs");
+const FOOTER_TEMPLATE: &str = include_str!("templates/footer.hbs");
+const DASHBOARD_TEMPLATE: &str = include_str!("templates/dashboard.hbs");
+const TASK_TEMPLATE: &str = include_str!("templates/task.hbs");
+
+#[derive(Deserialize, Debug)]
+pub struct TriggerForm {
+    /// The parameters of the new task, one `name=value` per line
+    parameters: String,
+}
+
+/// Overview of all running, queued and recently finished tasks
+pub async fn dashboard(data: web::Data<AppState>, request: web::HttpRequest) -> HttpResponse {
+    let authenticated = match verify_access(&data, &request, Scope::Read, None) {
+        Ok(authenticated) => authenticated,
+        Err(response) => return response,
+    };
+
+    // Restricted principals only see their own webhooks
+    let visible = get_visible_webhooks(&data.settings, &authenticated);
+    let overview = match data
+        .scheduler
+        .send(GetTasks {
+            finished_limit: FINISHED_LIMIT,
+            webhooks: visible.clone(),
+        })
+        .await
+    {
+        Ok(overview) => overview,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let tasks: Vec<Value> = overview
+        .running
+        .iter()
+        .chain(overview.queued.iter())
+        .chain(overview.scheduled.iter())
+        .chain(overview.finished.iter())
+        .map(get_task_row)
+        .collect();
+    let webhooks: Vec<&String> = data
+        .settings
+        .webhooks
+        .iter()
+        .map(|hook| &hook.name)
+        .filter(|name| {
+            visible
+                .as_ref()
+                .is_none_or(|visible| visible.contains(name))
+        })
+        .collect();
+
+    render_page(
+        DASHBOARD_TEMPLATE,
+        &json!({
+            "refresh": REFRESH_SECONDS,
+            "tasks": tasks,
+            "webhooks": webhooks,
+        }),
+    )
+}
+
+/// Details and output of a single task
+pub async fn task_details(
+    data: web::Data<AppState>,
+    path_info: web::Path<i32>,
+    request: web::HttpRequest,
+) -> HttpResponse {
+    // The task's webhook decides about the allowed clients and principals
+    let task = match get_task(&data, path_info.into_inner()).await {
+        Ok(task) => task,
+        Err(response) => return response,
+    };
+    if let Err(response) = verify_access(&data, &request, Scope::Read, Some(&task.webhook_name)) {
+        return response;
+    }
+
+    // The output of finished tasks might only exist in the log file
+    let output = match &task.log_file {
+        Some(path) if task.finished_at.is_some() => std::fs::read_to_string(path)
+            .unwrap_or_else(|error| format!("Couldn't read log file {:?}: {}", path, error)),
+        _ => task.stdout.clone().unwrap_or_default(),
+    };
+
+    let mut row = get_task_row(&task);
+    row["refresh"] = json!(REFRESH_SECONDS);
+    row["output"] = json!(output);
+    render_page(TASK_TEMPLATE, &row)
+}
+
+/// Trigger a new task of a webhook
+pub async fn trigger(
+    data: web::Data<AppState>,
+    path_info: web::Path<String>,
+    request: web::HttpRequest,
+    form: web::Form<TriggerForm>,
+) -> HttpResponse {
+    let webhook_name = path_info.into_inner();
+    let authenticated = match verify_access(&data, &request, Scope::Trigger, Some(&webhook_name)) {
+        Ok(authenticated) => authenticated,
+        Err(response) => return response,
+    };
+
+    let parameters = match parse_parameters(&form.parameters) {
+        Ok(parameters) => parameters,
+        Err(response) => return response,
+    };
+
+    submit_task(&data, &request, authenticated, webhook_name, parameters).await
+}
+
+/// Trigger a new task with the webhook and parameters of an existing task
+pub async fn rerun(
+    data: web::Data<AppState>,
+    path_info: web::Path<i32>,
+    request: web::HttpRequest,
+) -> HttpResponse {
+    // The task's webhook decides about the allowed clients and principals
+    let task = match get_task(&data, path_info.into_inner()).await {
+        Ok(task) => task,
+        Err(response) => return response,
+    };
+    let authenticated =
+        match verify_access(&data, &request, Scope::Trigger, Some(&task.webhook_name)) {
+            Ok(authenticated) => authenticated,
+            Err(response) => return response,
+        };
+
+    submit_task(
+        &data,
+        &request,
+        authenticated,
+        task.webhook_name,
+        task.parameters,
+    )
+    .await
+}
+
+/// Cancel a scheduled or queued task
+pub async fn cancel(
+    data: web::Data<AppState>,
+    path_info: web::Path<i32>,
+    request: web::HttpRequest,
+) -> HttpResponse {
+    // The task's webhook decides about the allowed clients and principals
+    let task_id = path_info.into_inner();
+    let task = match get_task(&data, task_id).await {
+        Ok(task) => task,
+        Err(response) => return response,
+    };
+    if let Err(response) = verify_access(&data, &request, Scope::Cancel, Some(&task.webhook_name)) {
+        return response;
+    }
+    match data.scheduler.send(CancelTask { task_id }).await {
+        Ok(true) => redirect(&format!("/dashboard/task/{}", task_id)),
+        Ok(false) => HttpResponse::NotFound()
+            .body(format!("No scheduled or queued task with id: {}", task_id)),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Check the credentials of the request.
+/// Actions additionally need to come from the dashboard itself, since browsers send the
+/// credentials of the basic auth along with requests of other sites.
+/// A signature of the empty body can be replayed, so actions need real credentials.
+fn verify_access(
+    data: &AppState,
+    request: &web::HttpRequest,
+    scope: Scope,
+    webhook: Option<&str>,
+) -> Result<Authenticated, HttpResponse> {
+    let headers = get_headers_hash_map(request.headers())?;
+
+    // Check the address of the client before its credentials
+    let allowed_ips = webhook
+        .map(|name| get_webhook_allowed_ips(&data.settings.webhooks, name))
+        .unwrap_or_default();
+    let client = verify_request_ip(&data.settings, request, &headers, allowed_ips)?;
+
+    let authenticated = authenticate(data, client, || {
+        verify_authentication_header(
+            &data.settings,
+            Provider::Generic,
+            &headers,
+            &[],
+            get_peer_certificate(request).as_ref(),
+            scope,
+            webhook,
+        )
+    })?;
+
+    if scope != Scope::Read {
+        require_credentials(&authenticated)?;
+        if let (Some(origin), Some(host)) = (headers.get("origin"), headers.get("host")) {
+            let origin_host = origin.split("://").nth(1).unwrap_or_default();
+            if origin_host != host {
+                warn!("Rejected dashboard action from foreign origin: {}", origin);
+                return Err(HttpResponse::Forbidden().body("Foreign origin"));
+            }
+        }
+    }
+
+    Ok(authenticated)
+}
+
+/// Send a new task to the scheduler and redirect to its page
+async fn submit_task(
+    data: &AppState,
+    request: &web::HttpRequest,
+    authenticated: Authenticated,
+    webhook_name: String,
+    parameters: HashMap<String, String>,
+) -> HttpResponse {
+    let headers = get_headers_hash_map(request.headers()).unwrap_or_default();
+    let mut audit_entry = AuditEntry::new(
+        request.peer_addr().map(|addr| addr.ip().to_string()),
+        headers.get("x-forwarded-for").cloned(),
+        get_request_id(&headers),
+        webhook_name.clone(),
+    );
+    audit_entry.principal = authenticated.principal;
+    audit_entry.auth_method = Some(authenticated.method);
+    let audit_log = &data.settings.audit_log;
+
+    // Tasks of the dashboard count towards the rate limit of the webhook as well
+    if let Ok(hook) = data.settings.get_webhook_by_name(&webhook_name) {
+        if let Err(response) = verify_rate_limit(data, &webhook_name, &hook.rate_limit) {
+            audit_entry.decision = Some(Decision::RateLimited);
+            write_audit_entry(audit_log, &audit_entry);
+            return response;
+        }
+    }
+
+    let payload = Payload {
+        parameters: Some(parameters),
+        ..Payload::default()
+    };
+    let new_task = match get_task_from_request(&data.settings, webhook_name.clone(), payload, &[]) {
+        Ok(task) => task,
+        Err(response) => {
+            audit_entry.decision = Some(Decision::Invalid);
+            write_audit_entry(audit_log, &audit_entry);
+            return response;
+        }
+    };
+    audit_entry.parameters = new_task.parameters.clone();
+    audit_entry.command = Some(new_task.command.clone());
+
+    let result = match data.scheduler.send(new_task).await {
+        Ok(result) => result,
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    info!(
+        webhook = webhook_name.as_str();
+        "Handled dashboard request: {:?}", result
+    );
+
+    let (decision, task_id) = match result {
+        NewTaskResult::Added(task_id) => (Decision::Queued, Some(task_id)),
+        NewTaskResult::Coalesced(task_id) => (Decision::Coalesced, Some(task_id)),
+        NewTaskResult::Rejected => (Decision::Rejected, None),
+    };
+    audit_entry.decision = Some(decision);
+    audit_entry.task_id = task_id;
+    write_audit_entry(audit_log, &audit_entry);
+
+    match task_id {
+        Some(task_id) => redirect(&format!("/dashboard/task/{}", task_id)),
+        None => HttpResponse::TooManyRequests().body("The queue is full"),
+    }
+}
+
+async fn get_task(data: &AppState, task_id: i32) -> Result<Task, HttpResponse> {
+    match data.scheduler.send(GetTask { task_id }).await {
+        Ok(Some(task)) => Ok(task),
+        Ok(None) => Err(HttpResponse::NotFound().body(format!("No task with id: {}", task_id))),
+        Err(_) => Err(HttpResponse::InternalServerError().finish()),
+    }
+}
+
+/// Parse the parameters of the trigger form. Empty lines are ignored.
+fn parse_parameters(input: &str) -> Result<HashMap<String, String>, HttpResponse> {
+    let mut parameters = HashMap::new();
+    for line in input.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        match line.split_once('=') {
+            Some((name, value)) => {
+                parameters.insert(name.trim().to_string(), value.trim().to_string());
+            }
+            None => {
+                return Err(HttpResponse::BadRequest()
+                    .body(format!("Parameters need to look like name=value: {}", line)))
+            }
+        }
+    }
+
+    Ok(parameters)
+}
+
+/// Get the state of a task for the status colours
+fn get_status(task: &Task) -> &'static str {
+    if task.finished_at.is_some() {
+        if task.exit_code == Some(0) {
+            "succeeded"
+        } else {
+            "failed"
+        }
+    } else if task.started_at.is_some() {
+        "running"
+    } else if task.run_at.is_some_and(|run_at| run_at > Local::now()) {
+        "scheduled"
+    } else {
+        "queued"
+    }
+}
+
+/// The data of a task for the templates
+fn get_task_row(task: &Task) -> Value {
+    json!({
+        "task": task,
+        "status": get_status(task),
+        "cancellable": task.started_at.is_none(),
+    })
+}
+
+/// Render a page. Values are html escaped.
+fn render_page(template: &str, data: &Value) -> HttpResponse {
+    let mut handlebars = Handlebars::new();
+    for (name, partial) in [("header", HEADER_TEMPLATE), ("footer", FOOTER_TEMPLATE)] {
+        if let Err(error) = handlebars.register_partial(name, partial) {
+            warn!("Couldn't register dashboard template {}: {}", name, error);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    match handlebars.render_template(template, data) {
+        Ok(html) => HttpResponse::Ok()
+            .append_header((http::header::CONTENT_TYPE, "text/html; charset=utf-8"))
+            .body(html),
+        Err(error) => {
+            warn!("Couldn't render dashboard: {}", error);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+fn redirect(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .append_header((http::header::LOCATION, location))
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_parameters;
+
+    #[test]
+    /// Parameters are parsed line by line and values may contain `=`
+    fn test_parse_parameters() {
+        let parameters = parse_parameters("branch = main\n\nflags=--a=b\n").unwrap();
+        assert_eq!(parameters.len(), 2);
+        assert_eq!(parameters["branch"], "main");
+        assert_eq!(parameters["flags"], "--a=b");
+
+        assert!(parse_parameters("invalid").is_err());
+    }
+}
@@ -1,17 +1,21 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use actix_web::http::header::HeaderMap;
 use actix_web::HttpResponse;
 use chrono::prelude::*;
-use handlebars::Handlebars;
+use chrono::Duration;
 use log::{info, warn};
 
 use crate::messages::NewTask;
 use crate::settings::Settings;
+use crate::task::step::render_steps;
+use crate::task::task::Commit;
+use crate::task::template::render_template;
 use crate::web::Payload;
 
 /// We do our own json handling, since Actix doesn't allow multiple extractors at once
-pub fn get_payload(body: &Vec<u8>) -> Result<Payload, HttpResponse> {
+pub fn get_payload(body: &[u8]) -> Result<Payload, HttpResponse> {
     match serde_json::from_slice(body) {
         Ok(payload) => Ok(payload),
         Err(error) => {
@@ -28,9 +32,8 @@ pub fn get_headers_hash_map(map: &HeaderMap) -> Result<HashMap<String, String>,
 
     for (key, header_value) in map.iter() {
         let key = key.as_str().to_string();
-        let value: String;
-        match header_value.to_str() {
-            Ok(header_value) => value = header_value.to_string(),
+        let value = match header_value.to_str() {
+            Ok(header_value) => header_value.to_string(),
             Err(error) => {
                 let message = format!("Couldn't parse header: {}", error);
                 warn!("{}", message);
@@ -44,20 +47,30 @@ pub fn get_headers_hash_map(map: &HeaderMap) -> Result<HashMap<String, String>,
     Ok(headers)
 }
 
+/// Counter for the ids of requests without a `X-Request-Id` header
+static REQUEST_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Get the id of a request for logging.
+/// Use the `X-Request-Id` header of the proxy web server, if there is one.
+pub fn get_request_id(headers: &HashMap<String, String>) -> String {
+    if let Some(request_id) = headers.get("x-request-id") {
+        return request_id.clone();
+    }
+
+    let count = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{}-{}", Local::now().timestamp(), count)
+}
+
 /// Verify that the template renders with the given parameters
 pub fn verify_template_parameters(
     template: String,
     parameters: &HashMap<String, String>,
 ) -> Result<String, HttpResponse> {
-    if parameters.len() != 0 {
+    if !parameters.is_empty() {
         info!("Got parameters: {:?}", parameters);
     }
-    // Create a new handlebar instance and enable strict mode to prevent missing or malformed arguments
-    let mut handlebars = Handlebars::new();
-    handlebars.set_strict_mode(true);
-
     // Check the template for render errors with the current parameter
-    let result = handlebars.render_template(&template, parameters);
+    let result = render_template(&template, parameters);
     match result {
         Err(error) => {
             warn!(
@@ -67,7 +80,7 @@ pub fn verify_template_parameters(
             Err(HttpResponse::BadRequest().json(format!("{:?}", error)))
         }
         Ok(result) => {
-            if parameters.len() != 0 {
+            if !parameters.is_empty() {
                 info!("Template renders properly: {}", result);
             }
             Ok(result)
@@ -75,22 +88,102 @@ pub fn verify_template_parameters(
     }
 }
 
+/// Determine when a task should be executed.
+/// Either `delay_seconds` or `run_at` can be specified, but not both.
+/// `None` means the task should be queued right away.
+pub fn get_run_at(
+    added_at: DateTime<Local>,
+    delay_seconds: Option<i64>,
+    run_at: Option<DateTime<Local>>,
+) -> Result<Option<DateTime<Local>>, HttpResponse> {
+    match (delay_seconds, run_at) {
+        (Some(_), Some(_)) => {
+            let message = "Only one of delay_seconds and run_at can be specified";
+            warn!("{}", message);
+            Err(HttpResponse::BadRequest().json(message))
+        }
+        (Some(delay), None) => {
+            if delay < 0 {
+                let message = format!("delay_seconds must not be negative: {}", delay);
+                warn!("{}", message);
+                return Err(HttpResponse::BadRequest().json(message));
+            }
+            Ok(Some(added_at + Duration::seconds(delay)))
+        }
+        (None, run_at) => Ok(run_at),
+    }
+}
+
+/// Get the repository and sha of the commit from a GitHub or Gitea push payload.
+/// Pushes that delete a branch point to the null sha and are ignored, as well as
+/// repositories and shas, that can't be used in the url of the API.
+pub fn get_commit(body: &[u8]) -> Option<Commit> {
+    let payload: serde_json::Value = serde_json::from_slice(body).ok()?;
+    let repository = payload.pointer("/repository/full_name")?.as_str()?;
+    let sha = payload
+        .get("after")
+        .or_else(|| payload.pointer("/head_commit/id"))?
+        .as_str()?;
+
+    if sha.is_empty() || sha.chars().all(|character| character == '0') {
+        return None;
+    }
+
+    let commit = Commit {
+        repository: repository.to_string(),
+        sha: sha.to_string(),
+    };
+    if !commit.is_valid() {
+        warn!(
+            "Ignoring commit with invalid repository or sha: {}@{}",
+            repository, sha
+        );
+        return None;
+    }
+
+    Some(commit)
+}
+
 /// Get a new task from a ingoing request
 pub fn get_task_from_request(
     settings: &Settings,
     name: String,
-    parameters: Option<HashMap<String, String>>,
+    payload: Payload,
+    body: &[u8],
 ) -> Result<NewTask, HttpResponse> {
-    let parameters = parameters.unwrap_or_default();
+    let parameters = payload.parameters.unwrap_or_default();
 
     let webhook = settings.get_webhook_by_name(&name)?;
     let command = verify_template_parameters(webhook.command, &parameters)?;
+    let steps = match render_steps(&webhook.steps, &webhook.cwd, &parameters) {
+        Ok(steps) => steps,
+        Err(error) => {
+            warn!(
+                "Error rendering steps with params: {:?}. Error: {:?}",
+                parameters, error
+            );
+            return Err(HttpResponse::BadRequest().json(format!("{:?}", error)));
+        }
+    };
+
+    let added_at = Local::now();
+    let run_at = get_run_at(added_at, payload.delay_seconds, payload.run_at)?;
+
+    // The commit is only needed to report its status
+    let commit = match webhook.commit_status {
+        Some(_) => get_commit(body),
+        None => None,
+    };
 
     Ok(NewTask {
         webhook_name: webhook.name,
         parameters,
         cwd: webhook.cwd,
         command,
-        added_at: Local::now(),
+        steps,
+        added_at,
+        run_at,
+        parent_id: None,
+        commit,
     })
 }
@@ -3,32 +3,49 @@ use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
 use std::str;
+use std::sync::{Arc, Mutex};
 
 use actix::prelude::*;
 use actix_web::*;
 use anyhow::{anyhow, bail, Context, Result};
+use chrono::prelude::*;
 use config::ConfigError;
-use rustls::{Certificate, PrivateKey, ServerConfig};
+use rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient};
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
 use rustls_pemfile::{pkcs8_private_keys, rsa_private_keys};
 use serde::Deserialize;
 
 mod authentication;
+mod certificate;
+mod dashboard;
 mod helper;
+mod network;
+mod provider;
+mod replay;
 mod routes;
+mod throttle;
 
 use crate::scheduler::Scheduler;
 use crate::settings::Settings;
+use replay::DeliveryCache;
 use routes::*;
+use throttle::{Lockouts, RateLimits};
 
 /// State of the actix-web application
 pub struct AppState {
     scheduler: Addr<Scheduler>,
     settings: Settings,
+    /// Shared by all workers, so replays are detected regardless of the worker
+    deliveries: Arc<Mutex<DeliveryCache>>,
+    lockouts: Arc<Mutex<Lockouts>>,
+    rate_limits: Arc<Mutex<RateLimits>>,
 }
 
 #[derive(Deserialize, Debug, Default)]
 pub struct Payload {
     parameters: Option<HashMap<String, String>>,
+    delay_seconds: Option<i64>,
+    run_at: Option<DateTime<Local>>,
 }
 
 /// Initialize the web server
@@ -36,15 +53,46 @@ pub struct Payload {
 /// of tasks to the actor
 pub fn init_web_server(scheduler: Addr<Scheduler>, settings: Settings) -> Result<()> {
     let settings_for_app = settings.clone();
+    let health_path = settings.health_path.clone();
+    let ready_path = settings.ready_path.clone();
+    let deliveries = Arc::new(Mutex::new(DeliveryCache::default()));
+    let lockouts = Arc::new(Mutex::new(Lockouts::new(&settings)));
+    let rate_limits = Arc::new(Mutex::new(RateLimits::default()));
     let server = HttpServer::new(move || {
         App::new()
             .app_data(AppState {
                 scheduler: scheduler.clone(),
                 settings: settings_for_app.clone(),
+                deliveries: deliveries.clone(),
+                lockouts: lockouts.clone(),
+                rate_limits: rate_limits.clone(),
             })
+            .service(web::resource("/task/{task_id}").route(web::delete().to(cancel_task)))
+            .service(web::resource("/dashboard").route(web::get().to(dashboard::dashboard)))
+            .service(
+                web::resource("/dashboard/task/{task_id}")
+                    .route(web::get().to(dashboard::task_details)),
+            )
+            .service(
+                web::resource("/dashboard/task/{task_id}/rerun")
+                    .route(web::post().to(dashboard::rerun)),
+            )
+            .service(
+                web::resource("/dashboard/task/{task_id}/cancel")
+                    .route(web::post().to(dashboard::cancel)),
+            )
+            .service(
+                web::resource("/dashboard/trigger/{webhook_name}")
+                    .route(web::post().to(dashboard::trigger)),
+            )
+            .service(web::resource("/metrics").to(metrics))
+            .service(web::resource("/stats").to(statistics))
+            .service(web::resource(health_path.as_str()).to(health))
+            .service(web::resource(ready_path.as_str()).to(ready))
             .service(web::resource("/{webhook_name}").to(webhook))
             .service(web::resource("/").to(index))
-    });
+    })
+    .on_connect(certificate::store_peer_certificate);
 
     let address = format!("{}:{}", settings.domain, settings.port);
 
@@ -54,12 +102,28 @@ pub fn init_web_server(scheduler: Addr<Scheduler>, settings: Settings) -> Result
             let certs = load_certs(&chain_path)?;
             let key = load_key(&key_path)?;
 
-            let config = ServerConfig::builder()
+            let builder = ServerConfig::builder()
                 .with_safe_default_cipher_suites()
                 .with_safe_default_kx_groups()
                 .with_safe_default_protocol_versions()
-                .expect("Couldn't enforce TLS1.2 and TLS 1.3. This is a bug.")
-                .with_no_client_auth()
+                .expect("Couldn't enforce TLS1.2 and TLS 1.3. This is a bug.");
+
+            // Verify client certificates against the CA, if one is specified
+            let builder = match &settings.ssl_client_ca {
+                Some(ca_path) => {
+                    let roots = load_roots(ca_path)?;
+                    if settings.ssl_client_cert_required {
+                        builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+                    } else {
+                        builder.with_client_cert_verifier(
+                            AllowAnyAnonymousOrAuthenticatedClient::new(roots),
+                        )
+                    }
+                }
+                None => builder.with_no_client_auth(),
+            };
+
+            let config = builder
                 .with_single_cert(certs, key)
                 .map_err(|err| anyhow!("Failed to build TLS Acceptor: {}", err))?;
 
@@ -91,6 +155,18 @@ fn load_certs(path: &Path) -> Result<Vec<Certificate>> {
     Ok(certs)
 }
 
+/// Load the certificates of the CA, that signs the client certificates
+fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certs(path)? {
+        roots
+            .add(&certificate)
+            .context(format!("Invalid CA certificate in {:?}", path))?;
+    }
+
+    Ok(roots)
+}
+
 /// Load the passed keys file.
 /// Only the first key will be used. It should match the certificate.
 fn load_key(path: &Path) -> Result<PrivateKey> {
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use actix_web::{HttpRequest, HttpResponse};
+use ipnet::IpNet;
+use log::warn;
+
+use crate::settings::{Settings, Webhook};
+
+/// Check the client of a request against the global and the webhook's `allowed_ips`.
+/// Returns the address of the client.
+pub fn verify_request_ip(
+    settings: &Settings,
+    request: &HttpRequest,
+    headers: &HashMap<String, String>,
+    webhook_allowed_ips: &[IpNet],
+) -> Result<Option<IpAddr>, HttpResponse> {
+    let peer = request.peer_addr().map(|address| address.ip());
+    let client = get_client_ip(&settings.trusted_proxies, peer, headers);
+    verify_client_ip(client, &settings.allowed_ips, webhook_allowed_ips)?;
+
+    Ok(client)
+}
+
+/// Get the `allowed_ips` of a webhook. Unknown webhooks don't restrict the client.
+pub fn get_webhook_allowed_ips<'a>(webhooks: &'a [Webhook], webhook: &str) -> &'a [IpNet] {
+    webhooks
+        .iter()
+        .find(|hook| hook.name == webhook)
+        .map(|hook| &hook.allowed_ips[..])
+        .unwrap_or_default()
+}
+
+/// Get the address of the client.
+/// `X-Forwarded-For` is only honoured, if the request comes from a trusted proxy.
+/// The header is read from right to left, since only the entries appended by trusted
+/// proxies can be relied on. The first untrusted address is the client.
+pub fn get_client_ip(
+    trusted_proxies: &[IpNet],
+    peer: Option<IpAddr>,
+    headers: &HashMap<String, String>,
+) -> Option<IpAddr> {
+    let mut client = peer?;
+    if !is_in_networks(trusted_proxies, client) {
+        return Some(client);
+    }
+
+    let forwarded_for = match headers.get("x-forwarded-for") {
+        Some(forwarded_for) => forwarded_for,
+        None => return Some(client),
+    };
+    for hop in forwarded_for.rsplit(',').map(str::trim) {
+        match hop.parse::<IpAddr>() {
+            Ok(address) => client = address,
+            Err(_) => {
+                warn!("Got invalid address in X-Forwarded-For: {}", hop);
+                break;
+            }
+        }
+        if !is_in_networks(trusted_proxies, client) {
+            break;
+        }
+    }
+
+    Some(client)
+}
+
+/// Check the address of the client against the global and the webhook's `allowed_ips`.
+/// Empty lists allow all addresses.
+pub fn verify_client_ip(
+    client: Option<IpAddr>,
+    global_allowed_ips: &[IpNet],
+    webhook_allowed_ips: &[IpNet],
+) -> Result<(), HttpResponse> {
+    for allowed_ips in [global_allowed_ips, webhook_allowed_ips] {
+        if allowed_ips.is_empty() {
+            continue;
+        }
+        if !client.is_some_and(|client| is_in_networks(allowed_ips, client)) {
+            warn!(
+                "Got request from address outside of allowed_ips: {:?}",
+                client
+            );
+            return Err(HttpResponse::Forbidden().body("Address isn't allowed"));
+        }
+    }
+
+    Ok(())
+}
+
+fn is_in_networks(networks: &[IpNet], address: IpAddr) -> bool {
+    // IPv4 clients of dual stack sockets show up as mapped IPv6 addresses
+    let address = match address {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(address),
+        IpAddr::V4(_) => address,
+    };
+
+    networks.iter().any(|network| network.contains(&address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn networks(networks: &[&str]) -> Vec<IpNet> {
+        networks
+            .iter()
+            .map(|network| network.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    /// Only hops added by trusted proxies are skipped
+    fn test_get_client_ip() {
+        let proxies = networks(&["10.0.0.0/8"]);
+        let mut headers = HashMap::new();
+        headers.insert(
+            "x-forwarded-for".to_string(),
+            "1.1.1.1, 2.2.2.2, 10.0.0.2".to_string(),
+        );
+
+        let proxy = Some("10.0.0.1".parse().unwrap());
+        assert_eq!(
+            get_client_ip(&proxies, proxy, &headers),
+            Some("2.2.2.2".parse().unwrap())
+        );
+
+        // Untrusted clients can't spoof their address
+        let client = Some("3.3.3.3".parse().unwrap());
+        assert_eq!(get_client_ip(&proxies, client, &headers), client);
+    }
+
+    #[test]
+    /// The client needs to be in the global and the webhook's list
+    fn test_verify_client_ip() {
+        let global = networks(&["192.168.0.0/16"]);
+        let webhook = networks(&["192.168.1.0/24"]);
+
+        assert!(verify_client_ip(Some("192.168.1.4".parse().unwrap()), &global, &webhook).is_ok());
+        assert!(verify_client_ip(Some("192.168.2.4".parse().unwrap()), &global, &webhook).is_err());
+        assert!(
+            verify_client_ip(Some("::ffff:192.168.2.4".parse().unwrap()), &global, &[]).is_ok()
+        );
+        assert!(verify_client_ip(None, &global, &[]).is_err());
+        assert!(verify_client_ip(None, &[], &[]).is_ok());
+    }
+
+    #[test]
+    /// Actions on existing tasks are restricted by the allowed_ips of the task's webhook
+    fn test_webhook_allowed_ips() {
+        let webhooks: Vec<Webhook> = serde_yaml::from_str(
+            "
+- {name: deploy, cwd: /tmp, command: deploy, allowed_ips: [192.168.1.0/24]}
+- {name: other, cwd: /tmp, command: other}
+",
+        )
+        .unwrap();
+        let allowed_ips = get_webhook_allowed_ips(&webhooks, "deploy");
+        assert_eq!(allowed_ips, &networks(&["192.168.1.0/24"])[..]);
+        assert!(get_webhook_allowed_ips(&webhooks, "other").is_empty());
+        assert!(get_webhook_allowed_ips(&webhooks, "unknown").is_empty());
+
+        let outside = Some("192.168.2.4".parse().unwrap());
+        assert!(verify_client_ip(outside, &[], allowed_ips).is_err());
+        assert!(verify_client_ip(outside, &[], &[]).is_ok());
+    }
+}
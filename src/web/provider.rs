@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+
+use actix_web::HttpResponse;
+use hmac::{Hmac, Mac, NewMac};
+use log::warn;
+use sha1::Sha1;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+use crate::settings::Provider;
+
+type HmacSha1 = Hmac<Sha1>;
+type HmacSha256 = Hmac<Sha256>;
+
+/// The way a request proves the knowledge of the secret
+#[derive(Debug, PartialEq)]
+pub enum Proof {
+    /// HMAC-SHA1 of the body
+    Sha1(Vec<u8>),
+    /// HMAC-SHA256 of the body
+    Sha256(Vec<u8>),
+    /// The secret itself
+    Token(String),
+    /// HMAC-SHA256 of the timestamp, a dot and the body
+    Timestamped { timestamp: i64, signature: Vec<u8> },
+}
+
+/// Headers with the unique id of a delivery. Redeliveries of the provider keep the id.
+const DELIVERY_HEADERS: [&str; 6] = [
+    "x-github-delivery",
+    "x-gitea-delivery",
+    "x-gogs-delivery",
+    "x-gitlab-event-uuid",
+    "x-hook-uuid",
+    "x-webhook-delivery",
+];
+
+impl Proof {
+    /// The name of the authentication method for logs
+    pub fn method(&self) -> &'static str {
+        match self {
+            Proof::Sha1(_) | Proof::Sha256(_) => "signature",
+            Proof::Timestamped { .. } => "timestamped_signature",
+            Proof::Token(_) => "token",
+        }
+    }
+}
+
+/// Extract the proof from the provider's headers.
+/// Returns `None`, if the request doesn't contain any of these headers.
+pub fn get_proof(
+    provider: Provider,
+    headers: &HashMap<String, String>,
+) -> Result<Option<Proof>, HttpResponse> {
+    let proof = match provider {
+        Provider::Generic => match headers
+            .get("signature")
+            .or_else(|| headers.get("x-hub-signature"))
+        {
+            Some(header) => Proof::Sha1(decode_signature(header, Some("sha1="))?),
+            None => return Ok(None),
+        },
+        Provider::Github => {
+            if let Some(header) = headers.get("x-hub-signature-256") {
+                Proof::Sha256(decode_signature(header, Some("sha256="))?)
+            } else if let Some(header) = headers.get("x-hub-signature") {
+                Proof::Sha1(decode_signature(header, Some("sha1="))?)
+            } else {
+                return Ok(None);
+            }
+        }
+        Provider::Gitlab => match headers.get("x-gitlab-token") {
+            Some(token) => Proof::Token(token.clone()),
+            None => return Ok(None),
+        },
+        Provider::Gitea => match headers
+            .get("x-gitea-signature")
+            .or_else(|| headers.get("x-gogs-signature"))
+        {
+            Some(header) => Proof::Sha256(decode_signature(header, None)?),
+            None => return Ok(None),
+        },
+        Provider::Bitbucket => match headers.get("x-hub-signature") {
+            Some(header) => Proof::Sha256(decode_signature(header, Some("sha256="))?),
+            None => return Ok(None),
+        },
+        Provider::Timestamped => match headers.get("x-webhook-signature") {
+            Some(header) => Proof::Timestamped {
+                timestamp: get_timestamp(headers)?,
+                signature: decode_signature(header, Some("sha256="))?,
+            },
+            None => return Ok(None),
+        },
+    };
+
+    Ok(Some(proof))
+}
+
+/// Verify the proof against the body and each of the secrets.
+/// Returns the id of the matching secret. All comparisons are done in constant time.
+pub fn verify_proof<'a>(
+    proof: &Proof,
+    secrets: &[(&'a str, &str)],
+    body: &[u8],
+) -> Result<&'a str, HttpResponse> {
+    // Check all secrets, so the time doesn't reveal which one matched
+    let mut matched = None;
+    for (id, secret) in secrets {
+        if matches_proof(proof, secret, body) {
+            matched = matched.or(Some(*id));
+        }
+    }
+
+    matched.ok_or_else(|| {
+        warn!("Got request with invalid {}", proof.method());
+        HttpResponse::Unauthorized().body(format!("Invalid {}", proof.method()))
+    })
+}
+
+fn matches_proof(proof: &Proof, secret: &str, body: &[u8]) -> bool {
+    match proof {
+        Proof::Sha1(signature) => generate_signature_sha1(secret.as_bytes(), body)
+            .verify(signature)
+            .is_ok(),
+        Proof::Sha256(signature) => generate_signature_sha256(secret.as_bytes(), body)
+            .verify(signature)
+            .is_ok(),
+        Proof::Token(token) => token.as_bytes().ct_eq(secret.as_bytes()).into(),
+        Proof::Timestamped {
+            timestamp,
+            signature,
+        } => {
+            let signed = [format!("{}.", timestamp).as_bytes(), body].concat();
+            generate_signature_sha256(secret.as_bytes(), &signed)
+                .verify(signature)
+                .is_ok()
+        }
+    }
+}
+
+/// Reject timestamped signatures, that are older or newer than the tolerance in seconds.
+/// Other proofs don't contain a timestamp and are always accepted.
+pub fn verify_timestamp(proof: &Proof, tolerance: i64, now: i64) -> Result<(), HttpResponse> {
+    if let Proof::Timestamped { timestamp, .. } = proof {
+        if (now - timestamp).abs() > tolerance {
+            warn!(
+                "Got request with timestamp outside of the tolerance: {}",
+                timestamp
+            );
+            return Err(HttpResponse::Unauthorized().body("Timestamp outside of the tolerance"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the id of the delivery, if the sender provides one
+pub fn get_delivery_id(headers: &HashMap<String, String>) -> Option<&String> {
+    DELIVERY_HEADERS.iter().find_map(|name| headers.get(*name))
+}
+
+/// Parse the unix timestamp of a timestamped signature
+fn get_timestamp(headers: &HashMap<String, String>) -> Result<i64, HttpResponse> {
+    let header = match headers.get("x-webhook-timestamp") {
+        Some(header) => header,
+        None => {
+            warn!("Got timestamped signature without timestamp");
+            return Err(HttpResponse::Unauthorized().body("No timestamp header found"));
+        }
+    };
+
+    header.trim().parse().map_err(|_| {
+        warn!("Error parsing timestamp: {}", header);
+        HttpResponse::Unauthorized().body("Invalid timestamp")
+    })
+}
+
+/// Strip the prefix of a signature header and decode the hex signature
+fn decode_signature(header: &str, prefix: Option<&str>) -> Result<Vec<u8>, HttpResponse> {
+    let signature = match prefix {
+        Some(prefix) => match header.strip_prefix(prefix) {
+            Some(signature) => signature,
+            None => {
+                warn!("Got request with missing {} prefix", prefix);
+                return Err(HttpResponse::Unauthorized()
+                    .body("Error while parsing signature: Couldn't find prefix"));
+            }
+        },
+        None => header,
+    };
+
+    match hex::decode(signature) {
+        Ok(signature) => Ok(signature),
+        Err(error) => {
+            warn!("Error decoding signature: {}, {}", signature, error);
+            Err(HttpResponse::Unauthorized().body("Invalid signature"))
+        }
+    }
+}
+
+/// Create a hmac SHA1 instance from a secret and body
+pub fn generate_signature_sha1(secret_bytes: &[u8], body: &[u8]) -> HmacSha1 {
+    let mut hmac =
+        HmacSha1::new_from_slice(secret_bytes).expect("Couldn't create hmac with current secret");
+    hmac.update(body);
+    hmac
+}
+
+/// Create a hmac SHA256 instance from a secret and body
+pub fn generate_signature_sha256(secret_bytes: &[u8], body: &[u8]) -> HmacSha256 {
+    let mut hmac =
+        HmacSha256::new_from_slice(secret_bytes).expect("Couldn't create hmac with current secret");
+    hmac.update(body);
+    hmac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "A secret string";
+    const BODY: &[u8] = b"{\"test\": \"A test body\"}";
+
+    fn sha256_hex() -> String {
+        hex::encode(
+            generate_signature_sha256(SECRET.as_bytes(), BODY)
+                .finalize()
+                .into_bytes(),
+        )
+    }
+
+    fn verify(provider: Provider, header: &str, value: String) -> bool {
+        let mut headers = HashMap::new();
+        headers.insert(header.to_string(), value);
+        match get_proof(provider, &headers) {
+            Ok(Some(proof)) => verify_proof(&proof, &[("test", SECRET)], BODY).is_ok(),
+            _ => false,
+        }
+    }
+
+    #[test]
+    /// Each provider finds the signature in its own header and encoding
+    fn test_provider_signatures() {
+        let prefixed = format!("sha256={}", sha256_hex());
+        assert!(verify(
+            Provider::Github,
+            "x-hub-signature-256",
+            prefixed.clone()
+        ));
+        assert!(verify(
+            Provider::Bitbucket,
+            "x-hub-signature",
+            prefixed.clone()
+        ));
+        assert!(verify(Provider::Gitea, "x-gitea-signature", sha256_hex()));
+        assert!(verify(Provider::Gitea, "x-gogs-signature", sha256_hex()));
+        assert!(verify(
+            Provider::Gitlab,
+            "x-gitlab-token",
+            SECRET.to_string()
+        ));
+
+        // Gitea signatures don't have a prefix
+        assert!(!verify(Provider::Gitea, "x-gitea-signature", prefixed));
+        assert!(!verify(
+            Provider::Gitlab,
+            "x-gitlab-token",
+            "wrong".to_string()
+        ));
+    }
+
+    #[test]
+    /// The timestamp is part of the signature and must be within the tolerance
+    fn test_timestamped_signature() {
+        let signed = [b"1700000000.".as_slice(), BODY].concat();
+        let signature = generate_signature_sha256(SECRET.as_bytes(), &signed);
+        let mut headers = HashMap::new();
+        headers.insert(
+            "x-webhook-signature".to_string(),
+            format!("sha256={}", hex::encode(signature.finalize().into_bytes())),
+        );
+        headers.insert("x-webhook-timestamp".to_string(), "1700000000".to_string());
+
+        let proof = get_proof(Provider::Timestamped, &headers)
+            .ok()
+            .flatten()
+            .unwrap();
+        assert!(verify_proof(&proof, &[("test", SECRET)], BODY).is_ok());
+        assert!(verify_timestamp(&proof, 300, 1700000200).is_ok());
+        assert!(verify_timestamp(&proof, 300, 1700000301).is_err());
+
+        // A changed timestamp invalidates the signature
+        headers.insert("x-webhook-timestamp".to_string(), "1700000200".to_string());
+        let proof = get_proof(Provider::Timestamped, &headers)
+            .ok()
+            .flatten()
+            .unwrap();
+        assert!(verify_proof(&proof, &[("test", SECRET)], BODY).is_err());
+    }
+
+    #[test]
+    /// Any of the secrets may match and its id is returned
+    fn test_multiple_secrets() {
+        let signature = generate_signature_sha256(SECRET.as_bytes(), BODY);
+        let proof = Proof::Sha256(signature.finalize().into_bytes().to_vec());
+
+        let secrets = [("new", "another secret"), ("old", SECRET)];
+        assert_eq!(verify_proof(&proof, &secrets, BODY).ok(), Some("old"));
+        assert!(verify_proof(&proof, &secrets[..1], BODY).is_err());
+        assert!(verify_proof(&proof, &[], BODY).is_err());
+    }
+
+    #[test]
+    /// Headers of other providers are ignored
+    fn test_foreign_header() {
+        let mut headers = HashMap::new();
+        headers.insert("x-gitlab-token".to_string(), SECRET.to_string());
+        assert_eq!(get_proof(Provider::Github, &headers).ok(), Some(None));
+    }
+}
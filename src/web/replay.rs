@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+
+/// Remembers the ids of recent deliveries to reject replayed requests
+#[derive(Debug, Default)]
+pub struct DeliveryCache {
+    /// The unix timestamp of the first delivery by id
+    seen: HashMap<String, i64>,
+}
+
+impl DeliveryCache {
+    /// Remember the id of a delivery.
+    /// Returns `false`, if the id has already been seen within the window in seconds.
+    pub fn insert(&mut self, delivery_id: &str, window: i64, now: i64) -> bool {
+        // Forget old deliveries, so the cache doesn't grow forever
+        self.seen.retain(|_, seen_at| now - *seen_at < window);
+
+        if self.seen.contains_key(delivery_id) {
+            return false;
+        }
+        self.seen.insert(delivery_id.to_string(), now);
+
+        true
+    }
+
+    /// Forget the id of a delivery, whose request hasn't been accepted.
+    /// Redeliveries with the same id are accepted again.
+    pub fn remove(&mut self, delivery_id: &str) {
+        self.seen.remove(delivery_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Ids are rejected within the window and accepted again afterwards
+    fn test_delivery_cache() {
+        let mut cache = DeliveryCache::default();
+        assert!(cache.insert("a", 60, 1000));
+        assert!(cache.insert("b", 60, 1010));
+        assert!(!cache.insert("a", 60, 1059));
+        assert!(cache.insert("a", 60, 1060));
+        assert_eq!(cache.seen.len(), 2);
+    }
+
+    #[test]
+    /// Redeliveries of rejected requests are accepted
+    fn test_rejected_delivery() {
+        let mut cache = DeliveryCache::default();
+        assert!(cache.insert("a", 60, 1000));
+        cache.remove("a");
+        assert!(cache.insert("a", 60, 1010));
+        assert!(!cache.insert("a", 60, 1020));
+    }
+}
@@ -1,13 +1,59 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
 use actix_web::http::Method;
 use actix_web::*;
 use actix_web::{http, HttpResponse};
-use log::{debug, info};
+use chrono::Local;
+use log::{debug, info, warn};
+use serde_json::json;
 
-use crate::messages::GetQueue;
-use crate::web::authentication::verify_authentication_header;
+use crate::audit::{write_audit_entry, AuditEntry, Decision};
+use crate::messages::{CancelTask, GetQueue, GetStatistics, GetTask, NewTaskResult, Ping};
+use crate::metrics::{render_metrics, REQUESTS};
+use crate::settings::{Provider, Scope};
+use crate::web::authentication::{
+    get_visible_webhooks, require_credentials, verify_authentication_header,
+};
+use crate::web::certificate::get_peer_certificate;
 use crate::web::helper::*;
+use crate::web::network::{get_webhook_allowed_ips, verify_request_ip};
+use crate::web::provider::get_delivery_id;
+use crate::web::throttle::{authenticate, verify_rate_limit};
 use crate::web::{AppState, Payload};
 
+/// The time in seconds a client should wait, before retrying a request that hit a full queue
+const RETRY_AFTER_SECONDS: u32 = 10;
+
+/// The time in seconds the scheduler has to answer a readiness check
+const READY_TIMEOUT_SECONDS: u64 = 5;
+
+/// Unauthenticated liveness check. Answers as long as the web server is running
+pub async fn health() -> HttpResponse {
+    HttpResponse::Ok().body("OK")
+}
+
+/// Unauthenticated readiness check.
+/// Checks that the scheduler responds in time and the worker pool isn't stalled.
+pub async fn ready(data: web::Data<AppState>) -> HttpResponse {
+    let ping = data
+        .scheduler
+        .send(Ping)
+        .timeout(Duration::from_secs(READY_TIMEOUT_SECONDS));
+
+    match ping.await {
+        Ok(true) => HttpResponse::Ok().body("OK"),
+        Ok(false) => {
+            warn!("Readiness check failed: The worker pool is stalled");
+            HttpResponse::ServiceUnavailable().body("The worker pool is stalled")
+        }
+        Err(error) => {
+            warn!("Readiness check failed: {}", error);
+            HttpResponse::ServiceUnavailable().body("The scheduler isn't responding")
+        }
+    }
+}
+
 /// Index route for getting current state of the server
 pub async fn index(data: web::Data<AppState>, request: web::HttpRequest) -> HttpResponse {
     let headers = match get_headers_hash_map(request.headers()) {
@@ -15,12 +61,31 @@ pub async fn index(data: web::Data<AppState>, request: web::HttpRequest) -> Http
         Err(response) => return response,
     };
 
+    // Check the address of the client before its credentials
+    let client = match verify_request_ip(&data.settings, &request, &headers, &[]) {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+
     // Check the credentials and signature headers of the request
-    if let Err(response) = verify_authentication_header(&data.settings, &headers, &Vec::new()) {
-        return response;
+    let authenticated = match authenticate(&data, client, || {
+        verify_authentication_header(
+            &data.settings,
+            Provider::Generic,
+            &headers,
+            &[],
+            get_peer_certificate(&request).as_ref(),
+            Scope::Read,
+            None,
+        )
+    }) {
+        Ok(authenticated) => authenticated,
+        Err(response) => return response,
     };
 
-    match data.scheduler.send(GetQueue {}).await {
+    // Restricted principals only see their own webhooks
+    let webhooks = get_visible_webhooks(&data.settings, &authenticated);
+    match data.scheduler.send(GetQueue { webhooks }).await {
         Ok(json) => HttpResponse::Ok()
             .append_header((http::header::CONTENT_TYPE, "application/json"))
             .body(json),
@@ -28,6 +93,78 @@ pub async fn index(data: web::Data<AppState>, request: web::HttpRequest) -> Http
     }
 }
 
+/// Aggregated statistics of finished tasks per webhook
+pub async fn statistics(data: web::Data<AppState>, request: web::HttpRequest) -> HttpResponse {
+    let headers = match get_headers_hash_map(request.headers()) {
+        Ok(headers) => headers,
+        Err(response) => return response,
+    };
+
+    // Check the address of the client before its credentials
+    let client = match verify_request_ip(&data.settings, &request, &headers, &[]) {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+
+    // Check the credentials and signature headers of the request
+    let authenticated = match authenticate(&data, client, || {
+        verify_authentication_header(
+            &data.settings,
+            Provider::Generic,
+            &headers,
+            &[],
+            get_peer_certificate(&request).as_ref(),
+            Scope::Read,
+            None,
+        )
+    }) {
+        Ok(authenticated) => authenticated,
+        Err(response) => return response,
+    };
+
+    // Restricted principals only see their own webhooks
+    let webhooks = get_visible_webhooks(&data.settings, &authenticated);
+    match data.scheduler.send(GetStatistics { webhooks }).await {
+        Ok(json) => HttpResponse::Ok()
+            .append_header((http::header::CONTENT_TYPE, "application/json"))
+            .body(json),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Expose metrics in the Prometheus text format
+pub async fn metrics(data: web::Data<AppState>, request: web::HttpRequest) -> HttpResponse {
+    let headers = match get_headers_hash_map(request.headers()) {
+        Ok(headers) => headers,
+        Err(response) => return response,
+    };
+
+    // Check the address of the client before its credentials
+    let client = match verify_request_ip(&data.settings, &request, &headers, &[]) {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+
+    // Check the credentials and signature headers of the request
+    if let Err(response) = authenticate(&data, client, || {
+        verify_authentication_header(
+            &data.settings,
+            Provider::Generic,
+            &headers,
+            &[],
+            get_peer_certificate(&request).as_ref(),
+            Scope::Read,
+            None,
+        )
+    }) {
+        return response;
+    };
+
+    HttpResponse::Ok()
+        .append_header((http::header::CONTENT_TYPE, "text/plain; version=0.0.4"))
+        .body(render_metrics())
+}
+
 /// Index route
 pub async fn webhook(
     data: web::Data<AppState>,
@@ -35,38 +172,249 @@ pub async fn webhook(
     request: web::HttpRequest,
     body: web::Bytes,
 ) -> HttpResponse {
+    let headers = match get_headers_hash_map(request.headers()) {
+        Ok(headers) => headers,
+        Err(response) => return response,
+    };
+
+    let webhook_name = path_info.into_inner();
+    let request_id = get_request_id(&headers);
+    let mut audit_entry = AuditEntry::new(
+        request.peer_addr().map(|addr| addr.ip().to_string()),
+        headers.get("x-forwarded-for").cloned(),
+        request_id.clone(),
+        webhook_name.clone(),
+    );
+    let audit_log = &data.settings.audit_log;
+
     let body: Vec<u8> = body.to_vec();
     let payload = match request.method() {
         &Method::POST => match get_payload(&body) {
             Ok(payload) => payload,
-            Err(response) => return response,
+            Err(response) => {
+                audit_entry.decision = Some(Decision::Invalid);
+                write_audit_entry(audit_log, &audit_entry);
+                return response;
+            }
         },
         _ => Payload::default(),
     };
 
-    let headers = match get_headers_hash_map(request.headers()) {
-        Ok(headers) => headers,
-        Err(response) => return response,
+    // Only use known webhook names as metric labels to keep the amount of labels bounded
+    let hook = data.settings.get_webhook_by_name(&webhook_name).ok();
+    let label = if hook.is_some() {
+        webhook_name.as_str()
+    } else {
+        "unknown"
     };
 
-    let webhook_name = path_info.into_inner();
+    // Check the address of the client before its credentials
+    let allowed_ips = hook
+        .as_ref()
+        .map(|hook| &hook.allowed_ips[..])
+        .unwrap_or_default();
+    let client = match verify_request_ip(&data.settings, &request, &headers, allowed_ips) {
+        Ok(client) => client,
+        Err(response) => {
+            REQUESTS.with_label_values(&[label, "failure"]).inc();
+            audit_entry.decision = Some(Decision::Blocked);
+            write_audit_entry(audit_log, &audit_entry);
+            return response;
+        }
+    };
 
     // Check the credentials and signature headers of the request
-    if let Err(response) = verify_authentication_header(&data.settings, &headers, &body) {
-        return response;
+    let provider = hook.as_ref().map(|hook| hook.provider).unwrap_or_default();
+    let authenticated = match authenticate(&data, client, || {
+        verify_authentication_header(
+            &data.settings,
+            provider,
+            &headers,
+            &body,
+            get_peer_certificate(&request).as_ref(),
+            Scope::Trigger,
+            Some(&webhook_name),
+        )
+    }) {
+        Ok(authenticated) => authenticated,
+        Err(response) => {
+            REQUESTS.with_label_values(&[label, "failure"]).inc();
+            // Locked out clients are rejected, before their credentials are checked
+            audit_entry.decision = if response.status() == http::StatusCode::TOO_MANY_REQUESTS {
+                Some(Decision::LockedOut)
+            } else {
+                Some(Decision::AuthFailed)
+            };
+            write_audit_entry(audit_log, &audit_entry);
+            return response;
+        }
+    };
+
+    // Limit the amount of accepted requests per webhook
+    if let Some(hook) = &hook {
+        if let Err(response) = verify_rate_limit(&data, label, &hook.rate_limit) {
+            REQUESTS.with_label_values(&[label, "failure"]).inc();
+            audit_entry.decision = Some(Decision::RateLimited);
+            write_audit_entry(audit_log, &audit_entry);
+            return response;
+        }
+    }
+
+    // Reject deliveries, that have already been handled.
+    // The id is forgotten again, if the request isn't accepted, so the provider can redeliver it.
+    let delivery_id = match verify_delivery_id(&data, &headers) {
+        Ok(delivery_id) => delivery_id,
+        Err(response) => {
+            REQUESTS.with_label_values(&[label, "failure"]).inc();
+            audit_entry.decision = Some(Decision::Replayed);
+            write_audit_entry(audit_log, &audit_entry);
+            return response;
+        }
     };
+    REQUESTS.with_label_values(&[label, "success"]).inc();
+    audit_entry.principal = authenticated.principal;
+    audit_entry.auth_method = Some(authenticated.method);
 
-    info!("Incoming webhook for \"{}\":", webhook_name);
+    info!(
+        webhook = label, request_id = request_id.as_str(),
+        principal = audit_entry.principal.as_deref().unwrap_or("anonymous");
+        "Incoming webhook for \"{}\":", webhook_name
+    );
     debug!("Got payload: {:?}", payload);
 
     // Create a new task with the checked parameters and webhook name
-    let new_task = match get_task_from_request(&data.settings, webhook_name, payload.parameters) {
+    let new_task = match get_task_from_request(&data.settings, webhook_name.clone(), payload, &body)
+    {
         Ok(task) => task,
-        Err(response) => return response,
+        Err(response) => {
+            forget_delivery_id(&data, delivery_id);
+            audit_entry.decision = Some(Decision::Invalid);
+            write_audit_entry(audit_log, &audit_entry);
+            return response;
+        }
     };
+    audit_entry.parameters = new_task.parameters.clone();
+    audit_entry.command = Some(new_task.command.clone());
 
     // Send the task to the actor managing the queue
-    data.scheduler.do_send(new_task);
+    let result = match data.scheduler.send(new_task).await {
+        Ok(result) => result,
+        Err(_) => {
+            forget_delivery_id(&data, delivery_id);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    info!(
+        webhook = label, request_id = request_id.as_str();
+        "Handled webhook request: {:?}", result
+    );
+
+    let (decision, task_id) = match result {
+        NewTaskResult::Added(task_id) => (Decision::Queued, Some(task_id)),
+        NewTaskResult::Coalesced(task_id) => (Decision::Coalesced, Some(task_id)),
+        NewTaskResult::Rejected => {
+            forget_delivery_id(&data, delivery_id);
+            (Decision::Rejected, None)
+        }
+    };
+    audit_entry.decision = Some(decision);
+    audit_entry.task_id = task_id;
+    write_audit_entry(audit_log, &audit_entry);
+
+    match result {
+        NewTaskResult::Added(task_id) => {
+            HttpResponse::Accepted().json(json!({ "task_id": task_id }))
+        }
+        NewTaskResult::Coalesced(task_id) => {
+            HttpResponse::Conflict().json(json!({ "task_id": task_id }))
+        }
+        NewTaskResult::Rejected => HttpResponse::TooManyRequests()
+            .insert_header((http::header::RETRY_AFTER, RETRY_AFTER_SECONDS.to_string()))
+            .json("The queue is full"),
+    }
+}
 
-    HttpResponse::Ok().finish()
+/// Cancel a scheduled or queued task, before it's started
+pub async fn cancel_task(
+    data: web::Data<AppState>,
+    path_info: web::Path<i32>,
+    request: web::HttpRequest,
+) -> HttpResponse {
+    let headers = match get_headers_hash_map(request.headers()) {
+        Ok(headers) => headers,
+        Err(response) => return response,
+    };
+
+    // The task's webhook decides about the allowed clients and principals
+    let task_id = path_info.into_inner();
+    let task = match data.scheduler.send(GetTask { task_id }).await {
+        Ok(Some(task)) => task,
+        Ok(None) => return HttpResponse::NotFound().json(format!("No task with id: {}", task_id)),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+    let webhook_name = task.webhook_name.as_str();
+
+    // Check the address of the client before its credentials
+    let allowed_ips = get_webhook_allowed_ips(&data.settings.webhooks, webhook_name);
+    let client = match verify_request_ip(&data.settings, &request, &headers, allowed_ips) {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+
+    // Check the credentials and signature headers of the request
+    let authenticated = match authenticate(&data, client, || {
+        verify_authentication_header(
+            &data.settings,
+            Provider::Generic,
+            &headers,
+            &[],
+            get_peer_certificate(&request).as_ref(),
+            Scope::Cancel,
+            Some(webhook_name),
+        )
+    }) {
+        Ok(authenticated) => authenticated,
+        Err(response) => return response,
+    };
+    if let Err(response) = require_credentials(&authenticated) {
+        return response;
+    }
+
+    match data.scheduler.send(CancelTask { task_id }).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound()
+            .json(format!("No scheduled or queued task with id: {}", task_id)),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Remember the delivery id of the request, if replay protection is enabled.
+/// Requests without a delivery id are accepted.
+/// Returns the remembered id, so it can be forgotten, if the request isn't accepted.
+fn verify_delivery_id(
+    data: &AppState,
+    headers: &HashMap<String, String>,
+) -> Result<Option<String>, HttpResponse> {
+    let (window, delivery_id) = match (data.settings.replay_window, get_delivery_id(headers)) {
+        (Some(window), Some(delivery_id)) => (window, delivery_id),
+        _ => return Ok(None),
+    };
+
+    let mut deliveries = match data.deliveries.lock() {
+        Ok(deliveries) => deliveries,
+        Err(_) => return Err(HttpResponse::InternalServerError().finish()),
+    };
+    if !deliveries.insert(delivery_id, window, Local::now().timestamp()) {
+        warn!("Got replayed delivery: {}", delivery_id);
+        return Err(HttpResponse::Unauthorized().body("Delivery has already been received"));
+    }
+
+    Ok(Some(delivery_id.clone()))
+}
+
+/// Forget the delivery id of a request, that hasn't been accepted
+fn forget_delivery_id(data: &AppState, delivery_id: Option<String>) {
+    if let (Some(delivery_id), Ok(mut deliveries)) = (delivery_id, data.deliveries.lock()) {
+        deliveries.remove(&delivery_id);
+    }
 }
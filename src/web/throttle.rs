@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+
+use actix_web::http::StatusCode;
+use actix_web::{http, HttpResponse};
+use chrono::Local;
+use log::warn;
+
+use crate::metrics::{AUTH_FAILURES, LOCKOUTS, RATE_LIMITED};
+use crate::settings::{RateLimit, Settings};
+use crate::web::authentication::Authenticated;
+use crate::web::AppState;
+
+/// The max amount of clients, whose failures are remembered.
+/// The client with the oldest failure is forgotten, once it's reached.
+const MAX_CLIENTS: usize = 10_000;
+
+/// The interval in seconds, in which clients without recent failures are forgotten
+const PRUNE_INTERVAL_SECONDS: i64 = 60;
+
+/// Failed authentications of a single client
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last_failure: i64,
+    locked_until: i64,
+}
+
+/// Counts failed authentications per client and locks out clients with too many of them
+#[derive(Debug)]
+pub struct Lockouts {
+    threshold: u32,
+    seconds: i64,
+    max_seconds: i64,
+    /// Failures by client address. IPv6 clients are grouped by their /64 network.
+    clients: HashMap<IpAddr, Failures>,
+    /// The time of the last removal of old failures
+    last_prune: i64,
+}
+
+impl Lockouts {
+    pub fn new(settings: &Settings) -> Self {
+        Lockouts {
+            threshold: settings.lockout_threshold,
+            seconds: settings.lockout_seconds,
+            max_seconds: settings.lockout_max_seconds,
+            clients: HashMap::new(),
+            last_prune: 0,
+        }
+    }
+
+    /// The remaining seconds of the client's lockout, if it's locked out
+    pub fn get_lockout(&self, client: IpAddr, now: i64) -> Option<i64> {
+        self.clients
+            .get(&get_client_key(client))
+            .map(|failures| failures.locked_until - now)
+            .filter(|remaining| *remaining > 0)
+    }
+
+    /// Count a failed authentication of the client.
+    /// Returns the seconds of the new lockout, if the client reached the threshold.
+    /// The lockout is doubled for each further failure.
+    pub fn record_failure(&mut self, client: IpAddr, now: i64) -> Option<i64> {
+        let client = get_client_key(client);
+        if now - self.last_prune >= PRUNE_INTERVAL_SECONDS {
+            self.prune(now);
+        }
+        if self.clients.len() >= MAX_CLIENTS && !self.clients.contains_key(&client) {
+            self.prune(now);
+            self.evict_oldest();
+        }
+
+        let failures = self.clients.entry(client).or_insert(Failures {
+            count: 0,
+            last_failure: now,
+            locked_until: 0,
+        });
+        failures.count += 1;
+        failures.last_failure = now;
+
+        if failures.count < self.threshold {
+            return None;
+        }
+        let exponent = failures.count - self.threshold;
+        let seconds = self
+            .seconds
+            .saturating_mul(2_i64.saturating_pow(exponent))
+            .min(self.max_seconds);
+        failures.locked_until = now + seconds;
+
+        Some(seconds)
+    }
+
+    /// Reset the failures of a client after a successful authentication
+    pub fn record_success(&mut self, client: IpAddr) {
+        self.clients.remove(&get_client_key(client));
+    }
+
+    /// Forget clients, that didn't fail for a long time
+    fn prune(&mut self, now: i64) {
+        let max_seconds = self.max_seconds;
+        self.clients
+            .retain(|_, failures| now - failures.last_failure < max_seconds);
+        self.last_prune = now;
+    }
+
+    /// Forget the client with the oldest failure to make room for a new one
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .clients
+            .iter()
+            .min_by_key(|(_, failures)| failures.last_failure)
+            .map(|(client, _)| *client);
+        if let Some(oldest) = oldest {
+            self.clients.remove(&oldest);
+        }
+    }
+}
+
+/// Get the address, under which the failures of a client are counted.
+/// IPv6 clients usually get a whole /64 network, so they're counted by network.
+fn get_client_key(client: IpAddr) -> IpAddr {
+    match client.to_canonical() {
+        IpAddr::V6(address) => {
+            let segments = address.segments();
+            IpAddr::V6(Ipv6Addr::new(
+                segments[0],
+                segments[1],
+                segments[2],
+                segments[3],
+                0,
+                0,
+                0,
+                0,
+            ))
+        }
+        address => address,
+    }
+}
+
+/// Counts the requests of each webhook in fixed windows
+#[derive(Debug, Default)]
+pub struct RateLimits {
+    /// The start of the current window and the amount of requests in it by webhook
+    windows: HashMap<String, (i64, u32)>,
+}
+
+impl RateLimits {
+    /// Count a request of the webhook.
+    /// Returns the seconds until the next window, if the limit is reached.
+    pub fn check(&mut self, webhook: &str, limit: &RateLimit, now: i64) -> Result<(), i64> {
+        let window = self.windows.entry(webhook.to_string()).or_insert((now, 0));
+        if now - window.0 >= limit.seconds {
+            *window = (now, 0);
+        }
+
+        if window.1 >= limit.requests {
+            return Err(window.0 + limit.seconds - now);
+        }
+        window.1 += 1;
+
+        Ok(())
+    }
+}
+
+/// Authenticate a request, unless its client is locked out.
+/// Failed authentications are counted and lock out the client, once there are too many.
+pub fn authenticate(
+    data: &AppState,
+    client: Option<IpAddr>,
+    verify: impl FnOnce() -> Result<Authenticated, HttpResponse>,
+) -> Result<Authenticated, HttpResponse> {
+    let client = match client {
+        Some(client) if data.settings.lockout_threshold > 0 => client,
+        _ => return count_failure(verify()),
+    };
+
+    let now = Local::now().timestamp();
+    if let Some(remaining) = lock(data)?.get_lockout(client, now) {
+        warn!("Rejected request of locked out client {}", client);
+        return Err(too_many_requests(
+            "Too many failed authentications",
+            remaining,
+        ));
+    }
+
+    // Don't hold the lock while verifying, since password hashes take a while
+    let result = count_failure(verify());
+    let mut lockouts = lock(data)?;
+    match &result {
+        Ok(_) => lockouts.record_success(client),
+        Err(response) if response.status() == StatusCode::UNAUTHORIZED => {
+            if let Some(seconds) = lockouts.record_failure(client, now) {
+                warn!(
+                    "Locked out client {} for {} seconds after too many failed authentications",
+                    client, seconds
+                );
+                LOCKOUTS.inc();
+            }
+        }
+        Err(_) => {}
+    }
+
+    result
+}
+
+/// Check the rate limit of a webhook, if it has one
+pub fn verify_rate_limit(
+    data: &AppState,
+    webhook: &str,
+    limit: &Option<RateLimit>,
+) -> Result<(), HttpResponse> {
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return Ok(()),
+    };
+
+    let mut rate_limits = match data.rate_limits.lock() {
+        Ok(rate_limits) => rate_limits,
+        Err(_) => return Err(HttpResponse::InternalServerError().finish()),
+    };
+    if let Err(retry_after) = rate_limits.check(webhook, limit, Local::now().timestamp()) {
+        warn!(webhook = webhook; "Rejected request due to the rate limit of webhook {}", webhook);
+        RATE_LIMITED.with_label_values(&[webhook]).inc();
+        return Err(too_many_requests("Rate limit exceeded", retry_after));
+    }
+
+    Ok(())
+}
+
+fn lock(data: &AppState) -> Result<std::sync::MutexGuard<'_, Lockouts>, HttpResponse> {
+    data.lockouts
+        .lock()
+        .map_err(|_| HttpResponse::InternalServerError().finish())
+}
+
+fn count_failure(
+    result: Result<Authenticated, HttpResponse>,
+) -> Result<Authenticated, HttpResponse> {
+    if let Err(response) = &result {
+        if response.status() == StatusCode::UNAUTHORIZED {
+            AUTH_FAILURES.inc();
+        }
+    }
+
+    result
+}
+
+fn too_many_requests(message: &str, retry_after: i64) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .append_header((http::header::RETRY_AFTER, retry_after.max(1).to_string()))
+        .body(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Clients are locked out at the threshold and the lockout doubles with each failure
+    fn test_lockout() {
+        let mut lockouts = Lockouts {
+            threshold: 3,
+            seconds: 60,
+            max_seconds: 200,
+            clients: HashMap::new(),
+            last_prune: 0,
+        };
+        let client = "10.0.0.1".parse().unwrap();
+
+        assert_eq!(lockouts.record_failure(client, 0), None);
+        assert_eq!(lockouts.record_failure(client, 1), None);
+        assert_eq!(lockouts.get_lockout(client, 1), None);
+        assert_eq!(lockouts.record_failure(client, 2), Some(60));
+        assert_eq!(lockouts.get_lockout(client, 12), Some(50));
+        assert_eq!(lockouts.get_lockout(client, 62), None);
+        assert_eq!(lockouts.record_failure(client, 62), Some(120));
+        assert_eq!(lockouts.record_failure(client, 182), Some(200));
+
+        // Other clients aren't affected
+        assert_eq!(lockouts.get_lockout("10.0.0.2".parse().unwrap(), 182), None);
+
+        lockouts.record_success(client);
+        assert_eq!(lockouts.get_lockout(client, 183), None);
+        assert_eq!(lockouts.record_failure(client, 183), None);
+    }
+
+    #[test]
+    /// IPv6 clients of the same /64 network share their failures
+    fn test_lockout_ipv6_network() {
+        let mut lockouts = Lockouts {
+            threshold: 2,
+            seconds: 60,
+            max_seconds: 200,
+            clients: HashMap::new(),
+            last_prune: 0,
+        };
+
+        assert_eq!(
+            lockouts.record_failure("2001:db8::1".parse().unwrap(), 0),
+            None
+        );
+        assert_eq!(
+            lockouts.record_failure("2001:db8::ffff:2".parse().unwrap(), 1),
+            Some(60)
+        );
+        assert_eq!(
+            lockouts.get_lockout("2001:db8::3".parse().unwrap(), 2),
+            Some(59)
+        );
+        assert_eq!(
+            lockouts.get_lockout("2001:db8:0:1::1".parse().unwrap(), 2),
+            None
+        );
+    }
+
+    #[test]
+    /// Old failures are pruned and the oldest client is forgotten, once the map is full
+    fn test_lockout_capacity() {
+        let mut lockouts = Lockouts {
+            threshold: 2,
+            seconds: 60,
+            max_seconds: 200,
+            clients: HashMap::new(),
+            last_prune: 0,
+        };
+        for index in 0..MAX_CLIENTS as u32 {
+            // The first client has the oldest failure
+            let now = if index == 0 { 0 } else { 10 };
+            lockouts.record_failure(IpAddr::from(index.to_be_bytes()), now);
+        }
+        assert_eq!(lockouts.clients.len(), MAX_CLIENTS);
+
+        // The oldest client makes room for the new one
+        lockouts.record_failure("10.255.0.1".parse().unwrap(), 20);
+        assert_eq!(lockouts.clients.len(), MAX_CLIENTS);
+        assert!(!lockouts.clients.contains_key(&"0.0.0.0".parse().unwrap()));
+
+        // Failures older than the max lockout are pruned
+        lockouts.record_failure("10.255.0.2".parse().unwrap(), 400);
+        assert_eq!(lockouts.clients.len(), 1);
+    }
+
+    #[test]
+    /// Requests beyond the limit are rejected until the window is over
+    fn test_rate_limit() {
+        let mut rate_limits = RateLimits::default();
+        let limit = RateLimit {
+            requests: 2,
+            seconds: 10,
+        };
+
+        assert_eq!(rate_limits.check("deploy", &limit, 0), Ok(()));
+        assert_eq!(rate_limits.check("deploy", &limit, 1), Ok(()));
+        assert_eq!(rate_limits.check("deploy", &limit, 4), Err(6));
+        assert_eq!(rate_limits.check("other", &limit, 4), Ok(()));
+        assert_eq!(rate_limits.check("deploy", &limit, 10), Ok(()));
+    }
+}